@@ -0,0 +1,141 @@
+#![deny(missing_docs)]
+
+use crate::error::JoinError;
+use crate::event::MembershipEvent;
+use crate::node::Node;
+use crate::result::Result;
+use crate::ProtocolConfig;
+use failure::format_err;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, oneshot};
+
+/// Asynchronous front-end of [Node](struct.Node.html) for applications running on tokio.
+///
+/// The protocol still runs on its own thread, results of calls and membership events are passed through async
+/// channels, so no call blocks the runtime. A member is configured as a [Node](struct.Node.html) before it is
+/// converted, e.g. to set its logger, metadata or delegate.
+pub struct AsyncNode {
+    node: Node,
+    done: Option<oneshot::Receiver<Result<()>>>,
+}
+
+impl From<Node> for AsyncNode {
+    fn from(node: Node) -> Self {
+        AsyncNode { node, done: None }
+    }
+}
+
+impl AsyncNode {
+    /// Creates new instance communicating with other members through `bind_address`.
+    pub fn new(bind_address: SocketAddr, config: ProtocolConfig) -> Self {
+        Node::new(bind_address, config).into()
+    }
+
+    /// Returns bind address of this member.
+    pub fn bind_address(&self) -> SocketAddr {
+        self.node.bind_address()
+    }
+
+    /// Starts new group.
+    ///
+    /// Fails with [ConfigError](enum.ConfigError.html) if the configuration is not valid.
+    pub async fn start(&mut self) -> Result<()> {
+        let (done, receiver) = oneshot::channel();
+        self.node.spawn(move |mut sync_node| {
+            // Nobody waiting for the result is not an error.
+            let _ = done.send(sync_node.start());
+            Ok(())
+        })?;
+        self.done = Some(receiver);
+        Ok(())
+    }
+
+    /// Joins the group through any of the `seeds` and returns members of the group once the first of them answers.
+    ///
+    /// Fails with [JoinError](enum.JoinError.html) if none of the seeds answers or none is given, see
+    /// [Node::join_any](struct.Node.html#method.join_any).
+    pub async fn join(&mut self, seeds: &[SocketAddr]) -> Result<Vec<SocketAddr>> {
+        if seeds.is_empty() {
+            return Err(JoinError::NoSeeds.into());
+        }
+        assert!(!seeds.contains(&self.bind_address()), "Can't join yourself");
+
+        let (joined, members) = oneshot::channel();
+        let (done, receiver) = oneshot::channel();
+        let seeds = seeds.to_vec();
+        self.node.spawn(move |mut sync_node| {
+            let joined = Box::new(move |members| {
+                let _ = joined.send(members);
+            });
+            let _ = done.send(sync_node.join(seeds, Some(joined)));
+            Ok(())
+        })?;
+        self.done = Some(receiver);
+        match members.await {
+            Ok(members) => Ok(members),
+            Err(_) => {
+                self.wait().await?;
+                Err(format_err!("Member stopped before joining the group"))
+            }
+        }
+    }
+
+    /// Stops this member, removing it from the group, see [Node::stop](struct.Node.html#method.stop).
+    pub async fn stop(&mut self) -> Result<()> {
+        // Protocol which has already stopped on its own cannot be asked to stop, its own error is more useful then.
+        let requested = self.node.request_stop();
+        self.wait().await?;
+        requested
+    }
+
+    /// Waits for the result of the protocol, the thread running it finishes right after sending it.
+    async fn wait(&mut self) -> Result<()> {
+        let done = self.done.take().expect("You have not joined yet");
+        self.node.detach();
+        done.await
+            .map_err(|e| format_err!("Membership thread panicked: {:?}", e))?
+    }
+
+    /// Returns all alive members of the group this member knows about.
+    pub async fn get_members(&self) -> Result<Vec<SocketAddr>> {
+        let (sender, receiver) = oneshot::channel();
+        self.node.call(move |sync_node| {
+            let _ = sender.send(sync_node.get_members_addresses());
+        })?;
+        receiver
+            .await
+            .map_err(|e| format_err!("Failed to get members: {:?}", e))
+    }
+
+    /// Returns stream of changes of the group observed by this member, see
+    /// [Node::subscribe](struct.Node.html#method.subscribe).
+    pub fn subscribe(&self) -> Result<MembershipEvents> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.node
+            .call(move |sync_node| sync_node.subscribe(Box::new(move |event| sender.send(event).is_ok())))?;
+        Ok(MembershipEvents { receiver })
+    }
+}
+
+/// Stream of [MembershipEvent](enum.MembershipEvent.html)s returned by
+/// [AsyncNode::subscribe](struct.AsyncNode.html#method.subscribe), ending when the member stops.
+pub struct MembershipEvents {
+    receiver: mpsc::UnboundedReceiver<MembershipEvent>,
+}
+
+impl MembershipEvents {
+    /// Returns the next event, or `None` once the member stopped.
+    pub async fn next(&mut self) -> Option<MembershipEvent> {
+        self.receiver.recv().await
+    }
+}
+
+impl futures_core::Stream for MembershipEvents {
+    type Item = MembershipEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
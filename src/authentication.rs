@@ -0,0 +1,74 @@
+use failure::Fail;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Size of the code authenticating a message, appended at its end.
+pub(crate) const MAC_SIZE: usize = 32;
+
+/// Message is not signed with the shared key.
+#[derive(Debug)]
+pub(crate) struct AuthenticationError;
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message authentication code is missing or does not match")
+    }
+}
+
+impl Fail for AuthenticationError {}
+
+/// Returns `message` followed by HMAC-SHA256 of it computed with `key`.
+pub(crate) fn sign(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(message);
+    let mut signed = Vec::with_capacity(message.len() + MAC_SIZE);
+    signed.extend_from_slice(message);
+    signed.extend_from_slice(&mac.result().code());
+    signed
+}
+
+/// Checks the code at the end of `signed` message and returns the message without it.
+pub(crate) fn verify<'a>(key: &[u8], signed: &'a [u8]) -> Result<&'a [u8], AuthenticationError> {
+    if signed.len() < MAC_SIZE {
+        return Err(AuthenticationError);
+    }
+    let (message, code) = signed.split_at(signed.len() - MAC_SIZE);
+    let mut mac = HmacSha256::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.input(message);
+    mac.verify(code).map_err(|_| AuthenticationError)?;
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn verify_signed_message() -> Result<(), AuthenticationError> {
+        let signed = sign(b"secret", b"message");
+        assert_eq!(signed.len(), b"message".len() + MAC_SIZE);
+        assert_eq!(verify(b"secret", &signed)?, b"message");
+        Ok(())
+    }
+
+    #[test]
+    fn reject_message_signed_with_other_key() {
+        let signed = sign(b"secret", b"message");
+        assert!(verify(b"other", &signed).is_err());
+    }
+
+    #[test]
+    fn reject_tampered_message() {
+        let mut signed = sign(b"secret", b"message");
+        signed[0] ^= 1;
+        assert!(verify(b"secret", &signed).is_err());
+    }
+
+    #[test]
+    fn reject_unsigned_message() {
+        assert!(verify(b"secret", b"short").is_err());
+    }
+}
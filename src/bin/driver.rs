@@ -3,6 +3,7 @@ use membership::{Node, ProtocolConfig};
 use sloggers::terminal::TerminalLoggerBuilder;
 use sloggers::Build;
 use std::net::SocketAddr;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -21,16 +22,35 @@ struct Options {
 
 #[derive(StructOpt)]
 struct ProtocolOptions {
-    #[structopt(short = "o", long = "proto-period", default_value = "5")]
-    pub protocol_period: u64,
+    #[structopt(
+        short = "o",
+        long = "proto-period",
+        default_value = "5",
+        parse(try_from_str = "parse_duration")
+    )]
+    pub protocol_period: Duration,
 
-    #[structopt(short = "a", long = "ack-timeout", default_value = "1")]
-    pub ack_timeout: u8,
+    #[structopt(
+        short = "a",
+        long = "ack-timeout",
+        default_value = "1",
+        parse(try_from_str = "parse_duration")
+    )]
+    pub ack_timeout: Duration,
 
     #[structopt(long = "num-indirect", default_value = "3")]
     pub num_indirect: u8,
 }
 
+/// Parses durations like `250ms` or `5s`, bare numbers are seconds.
+fn parse_duration(value: &str) -> Result<Duration, Error> {
+    if let Some(millis) = value.strip_suffix("ms") {
+        return Ok(Duration::from_millis(millis.parse()?));
+    }
+    let secs = value.strip_suffix('s').unwrap_or(value);
+    Ok(Duration::try_from_secs_f64(secs.parse()?)?)
+}
+
 impl From<ProtocolOptions> for ProtocolConfig {
     fn from(options: ProtocolOptions) -> Self {
         ProtocolConfig {
@@ -0,0 +1,50 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time of the protocol.
+///
+/// Members use the system clock, simulations share a virtual one which only moves when it is advanced, so that
+/// timeouts of the protocol do not have to be waited for.
+#[derive(Clone, Debug)]
+pub(crate) enum Clock {
+    System,
+    Virtual(Arc<Mutex<Instant>>),
+}
+
+impl Clock {
+    /// Creates virtual clock stopped at the current time.
+    pub(crate) fn new_virtual() -> Self {
+        Clock::Virtual(Arc::new(Mutex::new(Instant::now())))
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Virtual(now) => *now.lock().unwrap(),
+        }
+    }
+
+    /// Moves virtual clock forward by `duration`, system clock can not be advanced.
+    pub(crate) fn advance(&self, duration: Duration) {
+        match self {
+            Clock::System => panic!("System clock can not be advanced"),
+            Clock::Virtual(now) => *now.lock().unwrap() += duration,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn virtual_clock_moves_when_advanced() {
+        let clock = Clock::new_virtual();
+        let other = clock.clone();
+        let start = clock.now();
+        assert_eq!(other.now(), start);
+
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(other.now(), start + Duration::from_secs(3));
+    }
+}
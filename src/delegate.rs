@@ -0,0 +1,48 @@
+#![deny(missing_docs)]
+
+use crate::event::MemberInfo;
+
+/// Hooks of the application into the protocol, registered with
+/// [Node::set_delegate](struct.Node.html#method.set_delegate).
+///
+/// All methods are called on the thread running the protocol, so they should return quickly. Default
+/// implementations do nothing and allow everything.
+pub trait Delegate: Send {
+    /// Returns metadata advertised by this member when it starts, instead of the one set with
+    /// [Node::set_metadata](struct.Node.html#method.set_metadata).
+    ///
+    /// Metadata longer than `limit` bytes is ignored.
+    fn node_metadata(&mut self, _limit: usize) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Returns bytes piggybacked on the next protocol message sent to another member.
+    ///
+    /// At most `limit` bytes fit in the message, longer data is not sent. The data is delivered only to the
+    /// receiver of the message, it is up to the application to spread it further.
+    fn piggyback_data(&mut self, _limit: usize) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Receives bytes piggybacked by `sender` on a protocol message.
+    fn notify_piggyback_data(&mut self, _sender: &MemberInfo, _data: &[u8]) {}
+
+    /// Receives payload broadcast by another member with [Node::broadcast](struct.Node.html#method.broadcast).
+    fn notify_user_message(&mut self, _payload: &[u8]) {}
+
+    /// Decides whether `member` that this member learnt about is added to its view of the group.
+    fn allow_join(&mut self, _member: &MemberInfo) -> bool {
+        true
+    }
+
+    /// Decides whether state of the group received from `sender` during push-pull synchronisation is merged.
+    fn allow_merge(&mut self, _sender: &MemberInfo, _members: &[MemberInfo]) -> bool {
+        true
+    }
+
+    /// Notifies that `other` member uses the address of `existing` one under a different identifier.
+    ///
+    /// It happens when a member restarts before the group notices that it failed, but also when two members are
+    /// misconfigured to use the same address.
+    fn notify_conflict(&mut self, _existing: &MemberInfo, _other: &MemberInfo) {}
+}
@@ -0,0 +1,117 @@
+#![deny(missing_docs)]
+
+use failure::Fail;
+use std::fmt;
+use std::time::Duration;
+
+/// Errors of joining a group.
+#[derive(Debug)]
+pub enum JoinError {
+    /// No seed members were given.
+    NoSeeds,
+    /// None of the seed members answered, even after retrying.
+    NoSeedAnswered {
+        /// Number of times all seed members were tried.
+        attempts: u32,
+    },
+    /// Member has not joined the group in the given time.
+    Timeout,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::NoSeeds => write!(f, "At least one seed member is required"),
+            JoinError::NoSeedAnswered { attempts } => {
+                write!(f, "None of the seed members answered after {} attempts", attempts)
+            }
+            JoinError::Timeout => write!(f, "Timed out waiting to join the group"),
+        }
+    }
+}
+
+impl Fail for JoinError {}
+
+/// Errors of validating [ProtocolConfig](struct.ProtocolConfig.html).
+#[derive(Debug)]
+pub enum ConfigError {
+    /// `protocol_period` is zero.
+    ZeroProtocolPeriod,
+    /// `ack_timeout` is zero or not smaller than `protocol_period`.
+    AckTimeout {
+        /// Configured `ack_timeout`.
+        ack_timeout: Duration,
+        /// Configured `protocol_period`.
+        protocol_period: Duration,
+    },
+    /// `suspect_timeout` is shorter than `protocol_period`.
+    SuspectTimeout {
+        /// Configured `suspect_timeout`.
+        suspect_timeout: Duration,
+        /// Configured `protocol_period`.
+        protocol_period: Duration,
+    },
+    /// `num_indirect` is zero.
+    NoIndirectMembers,
+    /// Field that has to be greater than zero is zero.
+    Zero(&'static str),
+    /// `secret_key` is set but empty.
+    EmptySecretKey,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::ZeroProtocolPeriod => write!(f, "protocol_period must be greater than zero"),
+            ConfigError::AckTimeout {
+                ack_timeout,
+                protocol_period,
+            } => write!(
+                f,
+                "ack_timeout ({:?}) must be greater than zero and smaller than protocol_period ({:?})",
+                ack_timeout, protocol_period
+            ),
+            ConfigError::SuspectTimeout {
+                suspect_timeout,
+                protocol_period,
+            } => write!(
+                f,
+                "suspect_timeout ({:?}) must not be shorter than protocol_period ({:?})",
+                suspect_timeout, protocol_period
+            ),
+            ConfigError::NoIndirectMembers => write!(f, "num_indirect must be greater than zero"),
+            ConfigError::Zero(field) => write!(f, "{} must be greater than zero", field),
+            ConfigError::EmptySecretKey => write!(f, "secret_key must not be empty"),
+        }
+    }
+}
+
+impl Fail for ConfigError {}
+
+/// Errors of managing keys encrypting messages exchanged by members.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyringError {
+    /// Key is not 16 or 32 bytes long, as required by AES-128 and AES-256.
+    InvalidKeySize(usize),
+    /// Key has not been installed.
+    UnknownKey,
+    /// Key encrypting messages can not be removed, another one has to be used first.
+    PrimaryKey,
+    /// Messages are not encrypted, so keys can not be sent to other members.
+    NotEncrypted,
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyringError::InvalidKeySize(size) => write!(f, "Key of {} bytes is neither 16 nor 32 bytes long", size),
+            KeyringError::UnknownKey => write!(f, "Key has not been installed"),
+            KeyringError::PrimaryKey => write!(f, "Key encrypting messages can not be removed"),
+            KeyringError::NotEncrypted => {
+                write!(f, "Messages are not encrypted, keys can not be sent to other members")
+            }
+        }
+    }
+}
+
+impl Fail for KeyringError {}
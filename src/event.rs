@@ -0,0 +1,57 @@
+#![deny(missing_docs)]
+
+use crate::member::{Member, MemberId};
+use std::net::SocketAddr;
+
+/// Member of the group an event is about, as known when the event happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberInfo {
+    /// Identifier of the member, different for every start of a member even if its address is the same.
+    pub id: MemberId,
+    /// Address the member is reachable at.
+    pub address: SocketAddr,
+    /// Incarnation of the member, increased by the member itself to refute suspicions or announce new metadata.
+    pub incarnation: u64,
+}
+
+impl From<&Member> for MemberInfo {
+    fn from(member: &Member) -> Self {
+        MemberInfo {
+            id: member.id,
+            address: member.address,
+            incarnation: member.incarnation,
+        }
+    }
+}
+
+/// Change of the group observed by a member, delivered to receivers returned by
+/// [Node::subscribe](struct.Node.html#method.subscribe).
+#[derive(Debug, Clone, PartialEq)]
+pub enum MembershipEvent {
+    /// Member was added to the group.
+    Joined(MemberInfo),
+    /// Member did not answer a probe and is suspected to have failed.
+    Suspected(MemberInfo),
+    /// Suspected member refuted the suspicion by announcing a higher incarnation.
+    Alive(MemberInfo),
+    /// Suspicion of the member was confirmed and it was removed from the group.
+    Dead(MemberInfo),
+    /// Member left the group gracefully and was removed from it.
+    Left(MemberInfo),
+    /// Member announced a higher incarnation, e.g. because its metadata changed.
+    Updated(MemberInfo),
+}
+
+impl MembershipEvent {
+    /// Returns the member the event is about.
+    pub fn member(&self) -> &MemberInfo {
+        match self {
+            MembershipEvent::Joined(member)
+            | MembershipEvent::Suspected(member)
+            | MembershipEvent::Alive(member)
+            | MembershipEvent::Dead(member)
+            | MembershipEvent::Left(member)
+            | MembershipEvent::Updated(member) => member,
+        }
+    }
+}
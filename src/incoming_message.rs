@@ -1,7 +1,9 @@
 #![deny(missing_docs)]
 
+use crate::key_management::KeyMessage;
 use crate::member::Member;
 use crate::notification::Notification;
+use crate::user_message::UserMessage;
 
 #[derive(Debug)]
 pub(crate) struct DisseminationMessageIn {
@@ -9,6 +11,9 @@ pub(crate) struct DisseminationMessageIn {
     pub(crate) sequence_number: u64,
     pub(crate) notifications: Vec<Notification>,
     pub(crate) broadcast: Vec<Member>,
+    pub(crate) user_messages: Vec<UserMessage>,
+    pub(crate) key_messages: Vec<KeyMessage>,
+    pub(crate) delegate_data: Vec<u8>,
 }
 
 #[derive(Debug)]
@@ -18,17 +23,24 @@ pub(crate) struct PingRequestMessageIn {
     pub(crate) target: Member,
 }
 
+#[derive(Debug)]
+pub(crate) struct PushPullMessageIn {
+    pub(crate) sender: Member,
+    pub(crate) members: Vec<Notification>,
+}
+
 #[derive(Debug)]
 pub(crate) enum IncomingMessage {
     Ping(DisseminationMessageIn),
     Ack(DisseminationMessageIn),
     PingRequest(PingRequestMessageIn),
+    Nack(DisseminationMessageIn),
 }
 
 impl From<IncomingMessage> for DisseminationMessageIn {
     fn from(im: IncomingMessage) -> Self {
         match im {
-            IncomingMessage::Ping(message) | IncomingMessage::Ack(message) => message,
+            IncomingMessage::Ping(message) | IncomingMessage::Ack(message) | IncomingMessage::Nack(message) => message,
             _ => unreachable!(),
         }
     }
@@ -0,0 +1,174 @@
+#![deny(missing_docs)]
+
+use crate::error::KeyringError;
+use crate::keyring::Keyring;
+use crate::member::MemberId;
+use std::fmt;
+use std::net::SocketAddr;
+
+/// Change of the keyring requested from all members of the group.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum KeyOperation {
+    Install = 0,
+    Use = 1,
+    Remove = 2,
+}
+
+impl KeyOperation {
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(KeyOperation::Install),
+            1 => Some(KeyOperation::Use),
+            2 => Some(KeyOperation::Remove),
+            _ => None,
+        }
+    }
+
+    /// Applies the operation with `key` to `keyring`.
+    pub(crate) fn apply(self, keyring: &mut Keyring, key: &[u8]) -> Result<(), KeyringError> {
+        match self {
+            KeyOperation::Install => keyring.install(key.to_vec()),
+            KeyOperation::Use => keyring.use_key(key),
+            KeyOperation::Remove => keyring.remove(key),
+        }
+    }
+}
+
+/// Request to change keys and answers to it, piggybacked on the protocol messages like broadcast payloads.
+///
+/// A request is identified by the member that sent it and its sequence number, every member applies it to its own
+/// keyring and disseminates a response that eventually reaches the requesting member.
+#[derive(Clone, PartialEq)]
+pub(crate) enum KeyMessage {
+    Request {
+        origin: MemberId,
+        sequence_number: u64,
+        operation: KeyOperation,
+        key: Vec<u8>,
+    },
+    Response {
+        origin: MemberId,
+        sequence_number: u64,
+        member: MemberId,
+        result: Result<(), KeyringError>,
+    },
+}
+
+impl fmt::Debug for KeyMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyMessage::Request {
+                origin,
+                sequence_number,
+                operation,
+                ..
+            } => write!(
+                f,
+                "KeyMessage::Request {{ origin: {}, sequence_number: {}, operation: {:?} }}",
+                origin, sequence_number, operation
+            ),
+            KeyMessage::Response {
+                origin,
+                sequence_number,
+                member,
+                result,
+            } => write!(
+                f,
+                "KeyMessage::Response {{ origin: {}, sequence_number: {}, member: {}, result: {:?} }}",
+                origin, sequence_number, member, result
+            ),
+        }
+    }
+}
+
+impl KeyMessage {
+    /// Identifies the message, so it is handled only once.
+    pub(crate) fn id(&self) -> (MemberId, u64, Option<MemberId>) {
+        match self {
+            KeyMessage::Request {
+                origin,
+                sequence_number,
+                ..
+            } => (*origin, *sequence_number, None),
+            KeyMessage::Response {
+                origin,
+                sequence_number,
+                member,
+                ..
+            } => (*origin, *sequence_number, Some(*member)),
+        }
+    }
+}
+
+/// Encodes result of applying a key operation in a single byte.
+///
+/// The size of an invalid key is not sent, the requesting member knows it.
+pub(crate) fn encode_result(result: &Result<(), KeyringError>) -> u8 {
+    match result {
+        Ok(()) => 0,
+        Err(KeyringError::InvalidKeySize(_)) => 1,
+        Err(KeyringError::UnknownKey) => 2,
+        Err(KeyringError::PrimaryKey) => 3,
+        Err(KeyringError::NotEncrypted) => 4,
+    }
+}
+
+pub(crate) fn decode_result(value: u8) -> Option<Result<(), KeyringError>> {
+    match value {
+        0 => Some(Ok(())),
+        1 => Some(Err(KeyringError::InvalidKeySize(0))),
+        2 => Some(Err(KeyringError::UnknownKey)),
+        3 => Some(Err(KeyringError::PrimaryKey)),
+        4 => Some(Err(KeyringError::NotEncrypted)),
+        _ => None,
+    }
+}
+
+/// Outcome of changing keys on all members of the group.
+#[derive(Debug, Clone, Default)]
+pub struct KeyReport {
+    /// Members that applied the change, including the one that requested it.
+    pub succeeded: Vec<SocketAddr>,
+    /// Members that failed to apply the change, with the reason.
+    pub failed: Vec<(SocketAddr, KeyringError)>,
+    /// Members that did not answer in time.
+    pub missing: Vec<SocketAddr>,
+}
+
+impl KeyReport {
+    /// Returns whether all members known to the requesting member applied the change.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.missing.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ututils::create_member;
+
+    #[test]
+    fn encode_and_decode_results() {
+        let results = vec![
+            Ok(()),
+            Err(KeyringError::UnknownKey),
+            Err(KeyringError::PrimaryKey),
+            Err(KeyringError::NotEncrypted),
+        ];
+        for result in results {
+            assert_eq!(decode_result(encode_result(&result)), Some(result));
+        }
+        assert_eq!(decode_result(5), None);
+    }
+
+    #[test]
+    fn hide_key() {
+        let request = KeyMessage::Request {
+            origin: create_member(0).id,
+            sequence_number: 0,
+            operation: KeyOperation::Install,
+            key: vec![0xab; 16],
+        };
+        assert!(!format!("{:?}", request).contains(&format!("{:?}", vec![0xabu8; 16])));
+    }
+}
@@ -0,0 +1,188 @@
+use crate::error::KeyringError;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use failure::Fail;
+use rand::RngCore;
+use std::fmt;
+
+/// Size of the random nonce preceding every encrypted message.
+const NONCE_SIZE: usize = 12;
+
+/// Number of bytes added to a message by encrypting it, the nonce and authentication tag.
+pub(crate) const OVERHEAD: usize = NONCE_SIZE + 16;
+
+/// Message can not be decrypted with any of the installed keys.
+#[derive(Debug)]
+pub(crate) struct DecryptionError;
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message can not be decrypted with any of the installed keys")
+    }
+}
+
+impl Fail for DecryptionError {}
+
+/// Keys encrypting messages with AES-GCM.
+///
+/// The first key is the primary one, used to encrypt messages. All keys are tried to decrypt them, so keys can be
+/// rotated by installing a new key on all members, using it and only then removing the old one. Messages are not
+/// encrypted when there are no keys.
+#[derive(Clone, Default)]
+pub(crate) struct Keyring {
+    keys: Vec<Vec<u8>>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keyring {{ keys: {} }}", self.keys.len())
+    }
+}
+
+impl Keyring {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Adds `key` used to decrypt messages, the first installed key is also used to encrypt them.
+    pub(crate) fn install(&mut self, key: Vec<u8>) -> Result<(), KeyringError> {
+        if key.len() != 16 && key.len() != 32 {
+            return Err(KeyringError::InvalidKeySize(key.len()));
+        }
+        if !self.keys.contains(&key) {
+            self.keys.push(key);
+        }
+        Ok(())
+    }
+
+    /// Makes already installed `key` the primary one.
+    pub(crate) fn use_key(&mut self, key: &[u8]) -> Result<(), KeyringError> {
+        let index = self.position(key)?;
+        let key = self.keys.remove(index);
+        self.keys.insert(0, key);
+        Ok(())
+    }
+
+    /// Removes `key`, unless it is the primary one.
+    pub(crate) fn remove(&mut self, key: &[u8]) -> Result<(), KeyringError> {
+        match self.position(key)? {
+            0 => Err(KeyringError::PrimaryKey),
+            index => {
+                self.keys.remove(index);
+                Ok(())
+            }
+        }
+    }
+
+    fn position(&self, key: &[u8]) -> Result<usize, KeyringError> {
+        self.keys
+            .iter()
+            .position(|k| k.as_slice() == key)
+            .ok_or(KeyringError::UnknownKey)
+    }
+
+    /// Returns `message` encrypted with the primary key, preceded by a random nonce.
+    pub(crate) fn encrypt(&self, message: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = GenericArray::from_slice(&nonce);
+        let key = &self.keys[0];
+        let ciphertext = match key.len() {
+            16 => Aes128Gcm::new(GenericArray::from_slice(key)).encrypt(nonce, message),
+            _ => Aes256Gcm::new(GenericArray::from_slice(key)).encrypt(nonce, message),
+        }
+        .expect("Encryption of a message can not fail");
+        let mut encrypted = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        encrypted.extend_from_slice(nonce);
+        encrypted.extend_from_slice(&ciphertext);
+        encrypted
+    }
+
+    /// Decrypts message encrypted with any of the installed keys.
+    pub(crate) fn decrypt(&self, encrypted: &[u8]) -> Result<Vec<u8>, DecryptionError> {
+        if encrypted.len() < NONCE_SIZE {
+            return Err(DecryptionError);
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_SIZE);
+        let nonce = GenericArray::from_slice(nonce);
+        self.keys
+            .iter()
+            .filter_map(|key| match key.len() {
+                16 => Aes128Gcm::new(GenericArray::from_slice(key))
+                    .decrypt(nonce, ciphertext)
+                    .ok(),
+                _ => Aes256Gcm::new(GenericArray::from_slice(key))
+                    .decrypt(nonce, ciphertext)
+                    .ok(),
+            })
+            .next()
+            .ok_or(DecryptionError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keyring(keys: &[&[u8]]) -> Keyring {
+        let mut keyring = Keyring::default();
+        for key in keys {
+            keyring.install(key.to_vec()).unwrap();
+        }
+        keyring
+    }
+
+    #[test]
+    fn decrypt_encrypted_message() -> Result<(), DecryptionError> {
+        for key in &[&[1u8; 16][..], &[1u8; 32][..]] {
+            let keyring = keyring(&[key]);
+            let encrypted = keyring.encrypt(b"message");
+            assert_ne!(&encrypted[NONCE_SIZE..NONCE_SIZE + 7], b"message");
+            assert_eq!(keyring.decrypt(&encrypted)?, b"message");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decrypt_with_any_installed_key() -> Result<(), DecryptionError> {
+        let encrypted = keyring(&[&[2u8; 32]]).encrypt(b"message");
+        assert_eq!(keyring(&[&[1u8; 16], &[2u8; 32]]).decrypt(&encrypted)?, b"message");
+        Ok(())
+    }
+
+    #[test]
+    fn reject_message_encrypted_with_unknown_key() {
+        let encrypted = keyring(&[&[1u8; 32]]).encrypt(b"message");
+        assert!(keyring(&[&[2u8; 32]]).decrypt(&encrypted).is_err());
+        assert!(keyring(&[&[1u8; 32]]).decrypt(&encrypted[..NONCE_SIZE]).is_err());
+    }
+
+    #[test]
+    fn encrypt_with_used_key() -> Result<(), DecryptionError> {
+        let mut rotated = keyring(&[&[1u8; 32], &[2u8; 32]]);
+        rotated.use_key(&[2u8; 32]).unwrap();
+        rotated.remove(&[1u8; 32]).unwrap();
+        let encrypted = rotated.encrypt(b"message");
+        assert!(keyring(&[&[1u8; 32]]).decrypt(&encrypted).is_err());
+        assert_eq!(keyring(&[&[2u8; 32]]).decrypt(&encrypted)?, b"message");
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_operations() {
+        let mut keyring = keyring(&[&[1u8; 32]]);
+        match keyring.install(vec![1u8; 20]) {
+            Err(KeyringError::InvalidKeySize(20)) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        match keyring.use_key(&[2u8; 32]) {
+            Err(KeyringError::UnknownKey) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+        match keyring.remove(&[1u8; 32]) {
+            Err(KeyringError::PrimaryKey) => {}
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+}
@@ -15,34 +15,59 @@
 //! let mut ms2 = Node::new(SocketAddr::from_str("127.0.0.1:3456").unwrap(), Default::default());
 //! ms1.start().unwrap();
 //! ms2.join(SocketAddr::from_str("127.0.0.1:2345").unwrap()).unwrap();
-//! std::thread::sleep(Duration::from_secs(ProtocolConfig::default().protocol_period * 2));
+//! std::thread::sleep(ProtocolConfig::default().protocol_period * 2);
 //! println!("{:?}", ms1.get_members().unwrap());
 //! println!("{:?}", ms2.get_members().unwrap());
 //! ms1.stop().unwrap();
 //! ms2.stop().unwrap();
 //! ```
 
+#[cfg(feature = "tokio")]
+pub use crate::async_node::{AsyncNode, MembershipEvents};
+pub use crate::delegate::Delegate;
+pub use crate::error::{ConfigError, JoinError, KeyringError};
+pub use crate::event::{MemberInfo, MembershipEvent};
+pub use crate::key_management::KeyReport;
+pub use crate::member::MemberId;
+pub use crate::metrics::Metrics;
 pub use crate::node::Node;
-pub use crate::protocol_config::ProtocolConfig;
+pub use crate::protocol_config::{ProtocolConfig, ProtocolConfigBuilder};
+pub use crate::transport::{Readiness, Registry, Transport};
 
 /// Alias for backward compatibility. Please use [Node](struct.Node.html) instead.
 #[deprecated(since = "0.0.6", note = "Please use `Node` instead.")]
 pub type Membership = Node;
 
+#[cfg(feature = "tokio")]
+mod async_node;
+mod authentication;
+mod clock;
+mod delegate;
 mod disseminated;
+mod error;
+mod event;
 mod incoming_message;
+mod key_management;
+mod keyring;
 mod least_disseminated_members;
+mod local_health;
 mod member;
 mod message;
 mod message_decoder;
 mod message_encoder;
+mod metrics;
 mod node;
 mod notification;
 mod protocol_config;
+mod protocol_version;
+mod push_pull;
 mod result;
+pub mod simulation;
 mod suspicion;
 mod sync_node;
+mod transport;
 mod unique_circular_buffer;
+mod user_message;
 
 #[cfg(test)]
 mod ututils;
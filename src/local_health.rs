@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+/// Local Health Multiplier from Lifeguard extensions to SWIM.
+///
+/// The score grows when there are signs that this member itself is slow or has network problems (e.g. missed acks
+/// or being suspected by others) and shrinks when probes succeed. Timeouts are scaled by `score + 1`, so that
+/// a degraded member becomes less eager to suspect others.
+#[derive(Debug)]
+pub(crate) struct LocalHealth {
+    score: u32,
+    max_multiplier: u32,
+}
+
+impl LocalHealth {
+    pub(crate) fn new(max_multiplier: u32) -> Self {
+        LocalHealth {
+            score: 0,
+            max_multiplier: std::cmp::max(max_multiplier, 1),
+        }
+    }
+
+    pub(crate) fn increase(&mut self) {
+        self.score = std::cmp::min(self.score + 1, self.max_multiplier - 1);
+    }
+
+    pub(crate) fn decrease(&mut self) {
+        self.score = self.score.saturating_sub(1);
+    }
+
+    pub(crate) fn score(&self) -> u32 {
+        self.score
+    }
+
+    pub(crate) fn scale(&self, timeout: Duration) -> Duration {
+        timeout * (self.score + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn new() {
+        let health = LocalHealth::new(8);
+        assert_eq!(health.score(), 0);
+        assert_eq!(health.scale(Duration::from_secs(1)), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn increase_up_to_max_multiplier() {
+        let mut health = LocalHealth::new(3);
+        health.increase();
+        assert_eq!(health.scale(Duration::from_secs(1)), Duration::from_secs(2));
+        health.increase();
+        health.increase();
+        assert_eq!(health.score(), 2);
+        assert_eq!(health.scale(Duration::from_secs(1)), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn decrease_down_to_zero() {
+        let mut health = LocalHealth::new(3);
+        health.increase();
+        health.decrease();
+        health.decrease();
+        assert_eq!(health.score(), 0);
+    }
+
+    #[test]
+    fn disabled_with_single_multiplier() {
+        let mut health = LocalHealth::new(1);
+        health.increase();
+        assert_eq!(health.scale(Duration::from_secs(1)), Duration::from_secs(1));
+    }
+}
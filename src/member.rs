@@ -1,3 +1,4 @@
+use crate::protocol_version::{MAX_VERSION, MIN_VERSION};
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use sha1::{Digest, Sha1};
@@ -11,8 +12,12 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 type Incarnation = u64;
 
+/// Maximal size of metadata attached to a member.
+pub(crate) const MAX_METADATA_SIZE: usize = 128;
+
+/// Unique identifier of a member, generated from its address and start time.
 #[derive(Copy, Clone, PartialEq, Eq)]
-pub(crate) struct MemberId([u8; 20]);
+pub struct MemberId([u8; 20]);
 
 impl MemberId {
     pub(crate) fn as_slice(&self) -> &[u8] {
@@ -61,6 +66,9 @@ pub(crate) struct Member {
     pub(crate) id: MemberId,
     pub(crate) address: SocketAddr,
     pub(crate) incarnation: Incarnation,
+    pub(crate) metadata: Vec<u8>,
+    pub(crate) min_version: u8,
+    pub(crate) max_version: u8,
 }
 
 impl Member {
@@ -69,6 +77,9 @@ impl Member {
             id: generate_id(address),
             address,
             incarnation: 0,
+            metadata: Vec::new(),
+            min_version: MIN_VERSION,
+            max_version: MAX_VERSION,
         }
     }
 }
@@ -122,36 +133,54 @@ mod test {
             Member {
                 address,
                 id: member_id1,
-                incarnation: 1
+                incarnation: 1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
             Member {
                 address,
                 id: member_id1,
-                incarnation: 1
+                incarnation: 1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             }
         );
         assert_ne!(
             Member {
                 address,
                 id: member_id1,
-                incarnation: 1
+                incarnation: 1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
             Member {
                 address,
                 id: member_id1,
-                incarnation: 2
+                incarnation: 2,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             }
         );
         assert_ne!(
             Member {
                 address,
                 id: member_id1,
-                incarnation: 1
+                incarnation: 1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
             Member {
                 address,
                 id: member_id2,
-                incarnation: 1
+                incarnation: 1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             }
         );
     }
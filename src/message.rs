@@ -3,14 +3,16 @@
 use bytes::{Buf, BufMut, BytesMut};
 use std::fmt;
 use std::io::Cursor;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-// TODO: change repr to u8
+#[repr(u8)]
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub(super) enum MessageType {
     Ping,
     PingAck,
     PingIndirect,
+    PingNack,
+    PushPull,
 }
 
 pub(super) struct Message {
@@ -46,20 +48,21 @@ impl Message {
         self.buffer.resize(self.buffer.len() + 1, 0u8); // leave a byte for header
         let mut header = 0u8;
         let count = std::cmp::min(members.len(), std::mem::size_of_val(&header) * 8 - 1);
-        for idx in 0..count {
-            match members[idx] {
+        for (idx, member) in members.iter().take(count).enumerate() {
+            match member {
                 SocketAddr::V4(sa) => {
                     self.buffer.put_slice(&(sa.ip().octets()));
                     self.buffer.put_u16(sa.port());
                 }
-                SocketAddr::V6(_sa) => {
-                    panic!("IPv6 is not implemented yet.");
-                    //                    header |= 1 << idx;
+                SocketAddr::V6(sa) => {
+                    self.buffer.put_slice(&(sa.ip().octets()));
+                    self.buffer.put_u16(sa.port());
+                    header |= 1 << idx;
                 }
             }
         }
         header |= 1 << count as u8;
-        *self.buffer.iter_mut().skip(header_position).next().unwrap() = header;
+        self.buffer[header_position] = header;
         count
     }
 
@@ -69,6 +72,8 @@ impl Message {
             x if x == MessageType::Ping as i32 => MessageType::Ping,
             x if x == MessageType::PingAck as i32 => MessageType::PingAck,
             x if x == MessageType::PingIndirect as i32 => MessageType::PingIndirect,
+            x if x == MessageType::PingNack as i32 => MessageType::PingNack,
+            x if x == MessageType::PushPull as i32 => MessageType::PushPull,
             _ => panic!("No such message type"),
         }
     }
@@ -98,16 +103,13 @@ impl Message {
         let header = cursor.get_u8();
         let count = std::mem::size_of_val(&header) * 8 - header.leading_zeros() as usize - 1;
         let mut result = Vec::with_capacity(count as usize);
-        for _ in 0..count {
-            if (header & 1) == 0 {
-                result.push(SocketAddr::new(
-                    IpAddr::V4(Ipv4Addr::from(cursor.get_u32())),
-                    cursor.get_u16(),
-                ));
+        for idx in 0..count {
+            let ip = if (header >> idx) & 1 == 0 {
+                IpAddr::V4(Ipv4Addr::from(cursor.get_u32()))
             } else {
-                panic!("IPv6 is not implemented yet.");
-            }
-            let _ = header >> 1;
+                IpAddr::V6(Ipv6Addr::from(cursor.get_u128()))
+            };
+            result.push(SocketAddr::new(ip, cursor.get_u16()));
         }
         result
     }
@@ -122,11 +124,11 @@ impl Message {
         let header = cursor.get_u8();
         let count = std::mem::size_of_val(&header) * 8 - header.leading_zeros() as usize - 1;
         let mut result: usize = 0;
-        for _ in 0..count {
-            if (header & 1) == 0 {
+        for idx in 0..count {
+            if (header >> idx) & 1 == 0 {
                 result += std::mem::size_of::<u32>() + std::mem::size_of::<u16>();
             } else {
-                panic!("IPv6 is not implemented yet.")
+                result += std::mem::size_of::<u128>() + std::mem::size_of::<u16>();
             }
         }
         result as u64
@@ -257,6 +259,25 @@ mod test {
         assert_eq!(message.get_dead_members(), []);
     }
 
+    #[test]
+    fn add_mixed_members() {
+        let alive = [
+            SocketAddr::from_str("127.0.0.1:80").unwrap(),
+            SocketAddr::from_str("[::1]:8080").unwrap(),
+            SocketAddr::from_str("[fe80::1]:20000").unwrap(),
+        ];
+        let dead = [
+            SocketAddr::from_str("[2001:db8::1]:80").unwrap(),
+            SocketAddr::from_str("192.168.0.1:20000").unwrap(),
+        ];
+        let mut message = Message::create(MessageType::Ping, 1);
+        message.with_members(&alive, &dead);
+
+        assert_eq!(message.count_alive(), 3);
+        assert_eq!(message.get_alive_members(), alive);
+        assert_eq!(message.get_dead_members(), dead);
+    }
+
     #[test]
     fn from_bytes() {
         let message = Message::create(MessageType::PingAck, 1);
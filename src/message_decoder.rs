@@ -1,26 +1,64 @@
-use crate::incoming_message::{DisseminationMessageIn, IncomingMessage, PingRequestMessageIn};
-use crate::member::{Member, MemberId};
+use crate::incoming_message::{DisseminationMessageIn, IncomingMessage, PingRequestMessageIn, PushPullMessageIn};
+use crate::key_management::{self, KeyMessage, KeyOperation};
+use crate::member::{Member, MemberId, MAX_METADATA_SIZE};
 use crate::message::MessageType;
 use crate::notification::Notification;
+use crate::protocol_version::{CLUSTER_VERSION, DEFAULT_CLUSTER, MAGIC, MAX_VERSION, MIN_VERSION};
 use crate::result::Result;
+use crate::user_message::UserMessage;
 use bytes::Buf;
-use failure::format_err;
+use failure::{format_err, Fail};
 use std::convert::TryFrom;
+use std::fmt;
 use std::io::Cursor;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Message belongs to another group.
+#[derive(Debug)]
+pub(crate) struct ForeignClusterError {
+    pub(crate) cluster: u32,
+}
+
+impl fmt::Display for ForeignClusterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message belongs to another cluster: {:08x}", self.cluster)
+    }
+}
+
+impl Fail for ForeignClusterError {}
 
 struct MessageDecoder<'a> {
     buffer: Cursor<&'a [u8]>,
+    cluster: u32,
 }
 
 impl<'a> MessageDecoder<'a> {
-    fn decode(buffer: &[u8]) -> Result<IncomingMessage> {
+    fn decode(buffer: &[u8], cluster: u32) -> Result<IncomingMessage> {
         MessageDecoder {
             buffer: Cursor::new(buffer),
+            cluster,
         }
         .decode_message()
     }
 
+    fn decode_push_pull(buffer: &[u8], cluster: u32) -> Result<PushPullMessageIn> {
+        MessageDecoder {
+            buffer: Cursor::new(buffer),
+            cluster,
+        }
+        .decode_push_pull_message()
+    }
+
+    fn decode_push_pull_message(&mut self) -> Result<PushPullMessageIn> {
+        match self.decode_message_type()? {
+            MessageType::PushPull => Ok(PushPullMessageIn {
+                sender: self.decode_sender()?,
+                members: self.decode_members_state()?,
+            }),
+            x => Err(format_err!("Expected PushPull message, got {:?}", x)),
+        }
+    }
+
     fn decode_message(&mut self) -> Result<IncomingMessage> {
         let message_type = self.decode_message_type()?;
         let message = match message_type {
@@ -29,28 +67,71 @@ impl<'a> MessageDecoder<'a> {
                 sequence_number: self.decode_sequence_number()?,
                 notifications: self.decode_notifications()?,
                 broadcast: self.decode_broadcast()?,
+                user_messages: self.decode_user_messages()?,
+                key_messages: self.decode_key_messages()?,
+                delegate_data: self.decode_delegate_data()?,
             }),
             MessageType::PingAck => IncomingMessage::Ack(DisseminationMessageIn {
                 sender: self.decode_sender()?,
                 sequence_number: self.decode_sequence_number()?,
                 notifications: self.decode_notifications()?,
                 broadcast: self.decode_broadcast()?,
+                user_messages: self.decode_user_messages()?,
+                key_messages: self.decode_key_messages()?,
+                delegate_data: self.decode_delegate_data()?,
             }),
             MessageType::PingIndirect => IncomingMessage::PingRequest(PingRequestMessageIn {
                 sender: self.decode_sender()?,
                 sequence_number: self.decode_sequence_number()?,
                 target: self.decode_target()?,
             }),
+            MessageType::PingNack => IncomingMessage::Nack(DisseminationMessageIn {
+                sender: self.decode_sender()?,
+                sequence_number: self.decode_sequence_number()?,
+                notifications: self.decode_notifications()?,
+                broadcast: self.decode_broadcast()?,
+                user_messages: self.decode_user_messages()?,
+                key_messages: self.decode_key_messages()?,
+                delegate_data: self.decode_delegate_data()?,
+            }),
+            MessageType::PushPull => return Err(format_err!("PushPull message is not expected here")),
         };
         Ok(message)
     }
 
+    /// Decodes header of the message, i.e. magic byte, protocol version, group identifier and message type.
+    ///
+    /// Messages of versions older than `CLUSTER_VERSION` do not carry the group identifier, they can only come from
+    /// members of the unnamed group.
     fn decode_message_type(&mut self) -> Result<MessageType> {
-        let message_type = self.buffer.get_i32();
+        if self.buffer.remaining() < 3 * std::mem::size_of::<u8>() {
+            return Err(format_err!("Not enough bytes to discover message type"));
+        }
+        if self.buffer.get_u8() != MAGIC {
+            return Err(format_err!("Not a membership protocol message"));
+        }
+        let version = self.buffer.get_u8();
+        if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+            return Err(format_err!("Unsupported protocol version: {}", version));
+        }
+        let cluster = if version >= CLUSTER_VERSION {
+            if self.buffer.remaining() < std::mem::size_of::<u32>() + std::mem::size_of::<u8>() {
+                return Err(format_err!("Not enough bytes to discover message type"));
+            }
+            self.buffer.get_u32()
+        } else {
+            DEFAULT_CLUSTER
+        };
+        if cluster != self.cluster {
+            return Err(ForeignClusterError { cluster }.into());
+        }
+        let message_type = self.buffer.get_u8();
         match message_type {
-            x if x == MessageType::Ping as i32 => Ok(MessageType::Ping),
-            x if x == MessageType::PingAck as i32 => Ok(MessageType::PingAck),
-            x if x == MessageType::PingIndirect as i32 => Ok(MessageType::PingIndirect),
+            x if x == MessageType::Ping as u8 => Ok(MessageType::Ping),
+            x if x == MessageType::PingAck as u8 => Ok(MessageType::PingAck),
+            x if x == MessageType::PingIndirect as u8 => Ok(MessageType::PingIndirect),
+            x if x == MessageType::PingNack as u8 => Ok(MessageType::PingNack),
+            x if x == MessageType::PushPull as u8 => Ok(MessageType::PushPull),
             x => Err(format_err!("Unsupported message type: {}", x)),
         }
     }
@@ -89,13 +170,29 @@ impl<'a> MessageDecoder<'a> {
         let member = self.decode_member()?;
         let notification = match header & 0x0f {
             0 => Notification::Alive { member },
-            1 => Notification::Suspect { member },
+            1 => Notification::Suspect {
+                member,
+                from: self.decode_member_id()?,
+            },
             2 => Notification::Confirm { member },
+            3 => Notification::Leave { member },
             x => return Err(format_err!("Unsupported notification: {}", x)),
         };
         Ok(notification)
     }
 
+    fn decode_members_state(&mut self) -> Result<Vec<Notification>> {
+        if self.buffer.remaining() < std::mem::size_of::<u32>() {
+            return Err(format_err!("Could not decode number of members"));
+        }
+        let count = self.buffer.get_u32();
+        let mut result = Vec::new();
+        for _ in 0..count {
+            result.push(self.decode_notification()?);
+        }
+        Ok(result)
+    }
+
     fn decode_sender(&mut self) -> Result<Member> {
         if !self.buffer.has_remaining() {
             return Err(format_err!("Could not decode sender"));
@@ -104,6 +201,9 @@ impl<'a> MessageDecoder<'a> {
     }
 
     fn decode_member(&mut self) -> Result<Member> {
+        if !self.buffer.has_remaining() {
+            return Err(format_err!("Could not decode member address type"));
+        }
         let address_type = self.buffer.get_u8();
         let member_id = self.decode_member_id()?;
         if self.buffer.remaining() < std::mem::size_of::<u64>() {
@@ -111,13 +211,40 @@ impl<'a> MessageDecoder<'a> {
         }
         let incarnation = self.buffer.get_u64();
         let address = self.decode_address(address_type)?;
+        let metadata = self.decode_metadata()?;
+        if self.buffer.remaining() < 2 * std::mem::size_of::<u8>() {
+            return Err(format_err!("Could not decode member protocol versions"));
+        }
         Ok(Member {
             id: member_id,
             address,
             incarnation,
+            metadata,
+            min_version: self.buffer.get_u8(),
+            max_version: self.buffer.get_u8(),
         })
     }
 
+    fn decode_metadata(&mut self) -> Result<Vec<u8>> {
+        if !self.buffer.has_remaining() {
+            return Err(format_err!("Could not decode member metadata"));
+        }
+        let length = self.buffer.get_u8() as usize;
+        if length > MAX_METADATA_SIZE {
+            return Err(format_err!(
+                "Member metadata of {} bytes exceeds the limit of {} bytes",
+                length,
+                MAX_METADATA_SIZE
+            ));
+        }
+        if self.buffer.remaining() < length {
+            return Err(format_err!("Could not decode member metadata"));
+        }
+        let metadata = self.buffer.bytes()[..length].to_vec();
+        self.buffer.advance(length);
+        Ok(metadata)
+    }
+
     fn decode_member_id(&mut self) -> Result<MemberId> {
         if self.buffer.remaining() < std::mem::size_of::<MemberId>() {
             return Err(format_err!("Could not decode member id"));
@@ -131,24 +258,19 @@ impl<'a> MessageDecoder<'a> {
     }
 
     fn decode_address(&mut self, address_type: u8) -> Result<SocketAddr> {
-        // FIXME: The first value should depend on the `address_type`.
-        if self.buffer.remaining() < (std::mem::size_of::<u32>() + std::mem::size_of::<u16>()) {
+        let ip_size = match address_type {
+            0 => std::mem::size_of::<u32>(),
+            1 => std::mem::size_of::<u128>(),
+            x => return Err(format_err!("Unsupported address type: {}", x)),
+        };
+        if self.buffer.remaining() < (ip_size + std::mem::size_of::<u16>()) {
             return Err(format_err!("Could not decode member address"));
         }
-        let address = match address_type {
-            0 => SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(
-                    self.buffer.get_u8(),
-                    self.buffer.get_u8(),
-                    self.buffer.get_u8(),
-                    self.buffer.get_u8(),
-                )),
-                self.buffer.get_u16(),
-            ),
-            1 => return Err(format_err!("Support for IPv6 is not implemented yet")),
-            x => return Err(format_err!("Unsupported address type: {}", x)),
+        let ip = match address_type {
+            0 => IpAddr::V4(Ipv4Addr::from(self.buffer.get_u32())),
+            _ => IpAddr::V6(Ipv6Addr::from(self.buffer.get_u128())),
         };
-        Ok(address)
+        Ok(SocketAddr::new(ip, self.buffer.get_u16()))
     }
 
     fn decode_broadcast(&mut self) -> Result<Vec<Member>> {
@@ -163,6 +285,115 @@ impl<'a> MessageDecoder<'a> {
         Ok(result)
     }
 
+    fn decode_user_messages(&mut self) -> Result<Vec<UserMessage>> {
+        if !self.buffer.has_remaining() {
+            return Ok(Vec::new());
+        }
+        let count = self.buffer.get_u8();
+        let mut result = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            result.push(self.decode_user_message()?);
+        }
+        Ok(result)
+    }
+
+    fn decode_user_message(&mut self) -> Result<UserMessage> {
+        let origin = self.decode_member_id()?;
+        if self.buffer.remaining() < std::mem::size_of::<u64>() + std::mem::size_of::<u16>() {
+            return Err(format_err!("Could not decode user message"));
+        }
+        let sequence_number = self.buffer.get_u64();
+        let length = self.buffer.get_u16() as usize;
+        if self.buffer.remaining() < length {
+            return Err(format_err!("Could not decode user message payload"));
+        }
+        let payload = self.buffer.bytes()[..length].to_vec();
+        self.buffer.advance(length);
+        Ok(UserMessage {
+            origin,
+            sequence_number,
+            payload: payload.into(),
+        })
+    }
+
+    fn decode_key_messages(&mut self) -> Result<Vec<KeyMessage>> {
+        if !self.buffer.has_remaining() {
+            return Ok(Vec::new());
+        }
+        let count = self.buffer.get_u8();
+        let mut result = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            result.push(self.decode_key_message()?);
+        }
+        Ok(result)
+    }
+
+    fn decode_delegate_data(&mut self) -> Result<Vec<u8>> {
+        if !self.buffer.has_remaining() {
+            return Ok(Vec::new());
+        }
+        if self.buffer.remaining() < std::mem::size_of::<u16>() {
+            return Err(format_err!("Could not decode length of delegate data"));
+        }
+        let length = self.buffer.get_u16() as usize;
+        if self.buffer.remaining() < length {
+            return Err(format_err!("Could not decode delegate data"));
+        }
+        let data = self.buffer.bytes()[..length].to_vec();
+        self.buffer.advance(length);
+        Ok(data)
+    }
+
+    fn decode_key_message(&mut self) -> Result<KeyMessage> {
+        if !self.buffer.has_remaining() {
+            return Err(format_err!("Could not decode key message type"));
+        }
+        let message_type = self.buffer.get_u8();
+        let origin = self.decode_member_id()?;
+        if self.buffer.remaining() < std::mem::size_of::<u64>() {
+            return Err(format_err!("Could not decode key message sequence number"));
+        }
+        let sequence_number = self.buffer.get_u64();
+        match message_type {
+            0 => {
+                if self.buffer.remaining() < 2 * std::mem::size_of::<u8>() {
+                    return Err(format_err!("Could not decode key request"));
+                }
+                let operation = self.buffer.get_u8();
+                let operation = KeyOperation::from_u8(operation)
+                    .ok_or_else(|| format_err!("Unsupported key operation: {}", operation))?;
+                let length = self.buffer.get_u8() as usize;
+                if self.buffer.remaining() < length {
+                    return Err(format_err!("Could not decode key"));
+                }
+                let key = self.buffer.bytes()[..length].to_vec();
+                self.buffer.advance(length);
+                Ok(KeyMessage::Request {
+                    origin,
+                    sequence_number,
+                    operation,
+                    key,
+                })
+            }
+            1 => {
+                let member = self.decode_member_id()?;
+                if !self.buffer.has_remaining() {
+                    return Err(format_err!("Could not decode key response"));
+                }
+                let result = self.buffer.get_u8();
+                let result = key_management::decode_result(result)
+                    .ok_or_else(|| format_err!("Unsupported result of key operation: {}", result))?;
+                Ok(KeyMessage::Response {
+                    origin,
+                    sequence_number,
+                    member,
+                    result,
+                })
+            }
+            x => Err(format_err!("Unsupported key message: {}", x)),
+        }
+    }
+
     fn decode_target(&mut self) -> Result<Member> {
         if !self.buffer.has_remaining() {
             return Err(format_err!("Could not decode ping request target"));
@@ -171,10 +402,15 @@ impl<'a> MessageDecoder<'a> {
     }
 }
 
-pub(crate) fn decode_message(buffer: &[u8]) -> Result<IncomingMessage> {
+/// Decodes message of the group identified by `cluster`, see `protocol_version::cluster_hash`.
+pub(crate) fn decode_message(buffer: &[u8], cluster: u32) -> Result<IncomingMessage> {
     // 1. check protocol version in buffer
     // 2. create proper decoder
-    MessageDecoder::decode(buffer)
+    MessageDecoder::decode(buffer, cluster)
+}
+
+pub(crate) fn decode_push_pull_message(buffer: &[u8], cluster: u32) -> Result<PushPullMessageIn> {
+    MessageDecoder::decode_push_pull(buffer, cluster)
 }
 
 #[cfg(test)]
@@ -185,16 +421,22 @@ mod test {
 
     #[test]
     fn decode_empty_message() {
-        let mut buffer = BytesMut::with_capacity(47);
-        buffer.put_i32(MessageType::Ping as i32); // message type
+        let mut buffer = BytesMut::with_capacity(53);
+        buffer.put_u8(MAGIC); // magic byte
+        buffer.put_u8(MAX_VERSION); // protocol version
+        buffer.put_u32(0); // cluster
+        buffer.put_u8(MessageType::Ping as u8); // message type
         buffer.put_u8(0); // address type
         buffer.put_slice(&[0u8; 20]); // member id
         buffer.put_u64(0); // incarnation number
         buffer.put_slice(&[0u8; 4]); // IP address
         buffer.put_u16(0); // port
+        buffer.put_u8(0); // metadata length
+        buffer.put_u8(1); // min protocol version
+        buffer.put_u8(1); // max protocol version
         buffer.put_u64(42); // sequence number
 
-        match decode_message(&buffer).unwrap() {
+        match decode_message(&buffer, 0).unwrap() {
             IncomingMessage::Ping(message) => {
                 assert_eq!(message.sequence_number, 42);
                 assert_eq!(
@@ -202,74 +444,366 @@ mod test {
                     Member {
                         id: MemberId::try_from([0u8; 20].as_ref()).unwrap(),
                         address: SocketAddr::from_str("0.0.0.0:0").unwrap(),
-                        incarnation: 0
+                        incarnation: 0,
+                        metadata: Vec::new(),
+                        min_version: 1,
+                        max_version: 1,
                     }
                 );
             }
-            _ => assert!(false),
+            _ => panic!("Not a Ping message"),
         }
     }
 
     #[test]
-    fn decode_encoded_dissemination_message() -> Result<()> {
+    fn decode_version_1_message_of_unnamed_group() {
+        let mut buffer = BytesMut::with_capacity(49);
+        buffer.put_u8(MAGIC); // magic byte
+        buffer.put_u8(1); // protocol version, without cluster
+        buffer.put_u8(MessageType::Ping as u8); // message type
+        buffer.put_u8(0); // address type
+        buffer.put_slice(&[0u8; 20]); // member id
+        buffer.put_u64(0); // incarnation number
+        buffer.put_slice(&[0u8; 4]); // IP address
+        buffer.put_u16(0); // port
+        buffer.put_u8(0); // metadata length
+        buffer.put_u8(1); // min protocol version
+        buffer.put_u8(1); // max protocol version
+        buffer.put_u64(42); // sequence number
+
+        match decode_message(&buffer, DEFAULT_CLUSTER).unwrap() {
+            IncomingMessage::Ping(message) => assert_eq!(message.sequence_number, 42),
+            message => panic!("Unexpected message {:?}", message),
+        }
+        match decode_message(&buffer, 7) {
+            Err(e) => assert_eq!(
+                e.downcast_ref::<ForeignClusterError>().unwrap().cluster,
+                DEFAULT_CLUSTER
+            ),
+            Ok(message) => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn reject_foreign_message() {
+        let mut buffer = BytesMut::with_capacity(7);
+        buffer.put_u8(!MAGIC);
+        buffer.put_u8(MAX_VERSION);
+        buffer.put_u32(0);
+        buffer.put_u8(MessageType::Ping as u8);
+
+        assert!(decode_message(&buffer, 0).is_err());
+    }
+
+    #[test]
+    fn reject_message_of_other_cluster() -> Result<()> {
+        use crate::message_encoder::DisseminationMessageEncoder;
+
+        let encoded_message = DisseminationMessageEncoder::new(1024)
+            .cluster(7)
+            .message_type(MessageType::Ping)?
+            .sender(&Member::new(SocketAddr::from_str("127.0.0.1:2345")?))?
+            .sequence_number(0)?
+            .encode();
+        assert!(decode_message(encoded_message.buffer(), 7).is_ok());
+        match decode_message(encoded_message.buffer(), 8) {
+            Err(e) => assert_eq!(e.downcast_ref::<ForeignClusterError>().unwrap().cluster, 7),
+            Ok(message) => panic!("Unexpected message {:?}", message),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unsupported_version() -> Result<()> {
         use crate::message_encoder::DisseminationMessageEncoder;
 
         let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        for version in &[MIN_VERSION - 1, MAX_VERSION + 1] {
+            let encoded_message = DisseminationMessageEncoder::new(1024)
+                .version(*version)
+                .message_type(MessageType::Ping)?
+                .sender(&sender)?
+                .sequence_number(0)?
+                .encode();
+            match decode_message(encoded_message.buffer(), 0) {
+                Err(e) => assert_eq!(e.to_string(), format!("Unsupported protocol version: {}", version)),
+                Ok(message) => panic!("Unexpected message {:?}", message),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decode_encoded_dissemination_message() -> Result<()> {
+        use crate::message_encoder::DisseminationMessageEncoder;
+
+        let mut sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        sender.metadata = b"role=db".to_vec();
         let notifications = vec![
             Notification::Alive {
                 member: Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
             },
             Notification::Suspect {
                 member: Member::new(SocketAddr::from_str("127.0.1.2:5432")?),
+                from: sender.id,
+            },
+            Notification::Leave {
+                member: Member::new(SocketAddr::from_str("127.0.1.3:5432")?),
             },
         ];
         let broadcast = vec![
             Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
             Member::new(SocketAddr::from_str("127.0.1.2:5432")?),
         ];
+        let user_messages = vec![UserMessage {
+            origin: sender.id,
+            sequence_number: 7,
+            payload: bytes::Bytes::from_static(b"payload"),
+        }];
+        let key_messages = vec![
+            KeyMessage::Request {
+                origin: sender.id,
+                sequence_number: 3,
+                operation: KeyOperation::Use,
+                key: vec![1u8; 16],
+            },
+            KeyMessage::Response {
+                origin: sender.id,
+                sequence_number: 2,
+                member: broadcast[0].id,
+                result: Err(crate::error::KeyringError::UnknownKey),
+            },
+        ];
         let encoded_message = DisseminationMessageEncoder::new(1024)
             .message_type(MessageType::Ping)?
             .sender(&sender)?
             .sequence_number(24)?
             .notifications(notifications.iter())?
             .broadcast(broadcast.iter())?
+            .user_messages(user_messages.iter())?
+            .key_messages(key_messages.iter())?
+            .delegate_data(|_| b"delegate".to_vec())?
             .encode();
 
-        let decoded_message = decode_message(encoded_message.buffer())?;
+        let decoded_message = decode_message(encoded_message.buffer(), 0)?;
 
         if let IncomingMessage::Ping(ping_message) = decoded_message {
             assert_eq!(ping_message.sender, sender);
+            assert_eq!(ping_message.sender.metadata, sender.metadata);
             assert_eq!(ping_message.sequence_number, 24);
             assert_eq!(ping_message.notifications, notifications);
+            match ping_message.notifications[1] {
+                Notification::Suspect { from, .. } => assert_eq!(from, sender.id),
+                _ => panic!("Not a Suspect notification"),
+            }
             assert_eq!(ping_message.broadcast, broadcast);
+            assert_eq!(ping_message.user_messages, user_messages);
+            assert_eq!(ping_message.user_messages[0].payload, user_messages[0].payload);
+            assert_eq!(ping_message.key_messages, key_messages);
+            assert_eq!(ping_message.delegate_data, b"delegate");
         } else {
-            assert!(false, "Not a Ping message");
+            panic!("Not a Ping message");
         }
 
         Ok(())
     }
 
+    #[test]
+    fn decode_encoded_ipv6_members() -> Result<()> {
+        use crate::message_encoder::DisseminationMessageEncoder;
+
+        let sender = Member::new(SocketAddr::from_str("[::1]:2345")?);
+        let notifications = [Notification::Alive {
+            member: Member::new(SocketAddr::from_str("[2001:db8::1]:5432")?),
+        }];
+        let broadcast = [
+            Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
+            Member::new(SocketAddr::from_str("[2001:db8::2]:5432")?),
+        ];
+        let encoded_message = DisseminationMessageEncoder::new(1024)
+            .message_type(MessageType::PingAck)?
+            .sender(&sender)?
+            .sequence_number(24)?
+            .notifications(notifications.iter())?
+            .broadcast(broadcast.iter())?
+            .encode();
+
+        match decode_message(encoded_message.buffer(), 0)? {
+            IncomingMessage::Ack(message) => {
+                assert_eq!(message.sender.address, sender.address);
+                assert_eq!(
+                    message.notifications[0].member().address,
+                    notifications[0].member().address
+                );
+                assert_eq!(
+                    message.broadcast.iter().map(|m| m.address).collect::<Vec<_>>(),
+                    broadcast.iter().map(|m| m.address).collect::<Vec<_>>()
+                );
+            }
+            _ => panic!("Not an Ack message"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_truncated_ipv6_address() {
+        let mut buffer = BytesMut::with_capacity(46);
+        buffer.put_u8(MAGIC); // magic byte
+        buffer.put_u8(MAX_VERSION); // protocol version
+        buffer.put_u32(0); // cluster
+        buffer.put_u8(MessageType::Ping as u8); // message type
+        buffer.put_u8(1); // address type
+        buffer.put_slice(&[0u8; 20]); // member id
+        buffer.put_u64(0); // incarnation number
+        buffer.put_slice(&[0u8; 8]); // half of IP address
+        buffer.put_u16(0); // port
+
+        assert!(decode_message(&buffer, 0).is_err());
+    }
+
     #[test]
     fn decode_encoded_ping_request_message() -> Result<()> {
         use crate::message_encoder::PingRequestMessageEncoder;
 
         let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
         let target = Member::new(SocketAddr::from_str("127.0.1.1:5432")?);
-        let encoded_message = PingRequestMessageEncoder::new()
+        let encoded_message = PingRequestMessageEncoder::new(MAX_VERSION, 0)
             .sender(&sender)?
             .sequence_number(42)?
             .target(&target)?
             .encode();
 
-        let decoded_message = decode_message(encoded_message.buffer())?;
+        let decoded_message = decode_message(encoded_message.buffer(), 0)?;
 
         if let IncomingMessage::PingRequest(ping_request_message) = decoded_message {
             assert_eq!(ping_request_message.sender, sender);
             assert_eq!(ping_request_message.target, target);
         } else {
-            assert!(false, "Not a Ping message");
+            panic!("Not a Ping message");
         }
 
         Ok(())
     }
+
+    #[test]
+    fn decode_encoded_nack_message() -> Result<()> {
+        use crate::message_encoder::DisseminationMessageEncoder;
+
+        let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        let encoded_message = DisseminationMessageEncoder::new(1024)
+            .message_type(MessageType::PingNack)?
+            .sender(&sender)?
+            .sequence_number(42)?
+            .encode();
+
+        let decoded_message = decode_message(encoded_message.buffer(), 0)?;
+
+        if let IncomingMessage::Nack(nack_message) = decoded_message {
+            assert_eq!(nack_message.sender, sender);
+            assert_eq!(nack_message.sequence_number, 42);
+        } else {
+            panic!("Not a Nack message");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn decode_encoded_push_pull_message() -> Result<()> {
+        use crate::message_encoder::PushPullMessageEncoder;
+
+        let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        let members = vec![
+            Notification::Alive {
+                member: Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
+            },
+            Notification::Suspect {
+                member: Member::new(SocketAddr::from_str("127.0.1.2:5432")?),
+                from: sender.id,
+            },
+        ];
+        let encoded_message = PushPullMessageEncoder::new(1024, MAX_VERSION, 0)
+            .sender(&sender)?
+            .members(&members)?
+            .encode();
+
+        let decoded_message = decode_push_pull_message(encoded_message.buffer(), 0)?;
+        assert_eq!(decoded_message.sender, sender);
+        assert_eq!(decoded_message.members, members);
+
+        assert!(decode_message(encoded_message.buffer(), 0).is_err());
+
+        Ok(())
+    }
+
+    fn encode_push_pull_message() -> Result<Vec<u8>> {
+        use crate::message_encoder::PushPullMessageEncoder;
+
+        let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        let members = vec![
+            Notification::Alive {
+                member: Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
+            },
+            Notification::Suspect {
+                member: Member::new(SocketAddr::from_str("[::1]:5432")?),
+                from: sender.id,
+            },
+        ];
+        Ok(PushPullMessageEncoder::new(1024, MAX_VERSION, 0)
+            .sender(&sender)?
+            .members(&members)?
+            .encode()
+            .buffer()
+            .to_vec())
+    }
+
+    #[test]
+    fn reject_truncated_push_pull_message() -> Result<()> {
+        let buffer = encode_push_pull_message()?;
+        for length in 0..buffer.len() {
+            assert!(decode_push_pull_message(&buffer[..length], 0).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_member_with_too_large_metadata() -> Result<()> {
+        use crate::message_encoder::PushPullMessageEncoder;
+
+        let encode = |metadata_size| -> Result<Vec<u8>> {
+            let mut sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+            sender.metadata = vec![1; metadata_size];
+            Ok(PushPullMessageEncoder::new(1024, MAX_VERSION, 0)
+                .sender(&sender)?
+                .members(&[])?
+                .encode()
+                .buffer()
+                .to_vec())
+        };
+
+        assert!(decode_push_pull_message(&encode(MAX_METADATA_SIZE)?, 0).is_ok());
+        assert!(decode_push_pull_message(&encode(MAX_METADATA_SIZE + 1)?, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_corrupted_push_pull_message_without_panic() -> Result<()> {
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        let buffer = encode_push_pull_message()?;
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10_000 {
+            let mut corrupted = buffer.clone();
+            corrupted.truncate(rng.gen_range(0, buffer.len() + 1));
+            for _ in 0..rng.gen_range(1, 4) {
+                if !corrupted.is_empty() {
+                    let index = rng.gen_range(0, corrupted.len());
+                    corrupted[index] = rng.gen();
+                }
+            }
+            let _ = decode_push_pull_message(&corrupted, 0);
+        }
+        Ok(())
+    }
 }
@@ -1,7 +1,10 @@
-use crate::member::Member;
+use crate::key_management::{self, KeyMessage};
+use crate::member::{Member, MemberId};
 use crate::message::MessageType;
 use crate::notification::Notification;
+use crate::protocol_version::{CLUSTER_VERSION, MAGIC, MAX_VERSION};
 use crate::result::Result;
+use crate::user_message::UserMessage;
 use bytes::buf::ext::{BufMutExt, Limit};
 use bytes::{BufMut, Bytes, BytesMut};
 use failure::_core::marker::PhantomData;
@@ -22,10 +25,10 @@ pub(crate) struct PingRequestMessageEncoder {
 }
 
 impl PingRequestMessageEncoder {
-    pub(crate) fn new() -> SenderEncoder<SequenceNumberEncoder<TargetEncoder<Self>>> {
+    pub(crate) fn new(version: u8, cluster: u32) -> SenderEncoder<SequenceNumberEncoder<TargetEncoder<Self>>> {
         // TODO: calculate exact max length as capacity
         let mut buffer = BytesMut::with_capacity(1024).limit(1024);
-        buffer.put_i32(MessageType::PingIndirect as i32);
+        encode_header(version, cluster, MessageType::PingIndirect, &mut buffer);
         SenderEncoder::<SequenceNumberEncoder<TargetEncoder<Self>>>::from(buffer)
     }
 
@@ -42,14 +45,53 @@ impl From<Limit<BytesMut>> for PingRequestMessageEncoder {
     }
 }
 
+pub(crate) struct PushPullMessageEncoder {
+    buffer: Limit<BytesMut>,
+}
+
+impl PushPullMessageEncoder {
+    #[allow(clippy::new_ret_no_self)]
+    pub(crate) fn new(max_size: usize, version: u8, cluster: u32) -> SenderEncoder<Self> {
+        let mut buffer = BytesMut::with_capacity(1024).limit(max_size);
+        encode_header(version, cluster, MessageType::PushPull, &mut buffer);
+        SenderEncoder::<Self>::from(buffer)
+    }
+
+    pub(crate) fn members(mut self, members: &[Notification]) -> Result<Self> {
+        if self.buffer.remaining_mut() < std::mem::size_of::<u32>() {
+            return Err(format_err!("Could not encode number of members"));
+        }
+        self.buffer.put_u32(members.len() as u32);
+        for member in members {
+            encode_notification(member, &mut self.buffer)?;
+        }
+        Ok(self)
+    }
+
+    pub(crate) fn encode(self) -> OutgoingMessage {
+        OutgoingMessage::PushPullMessage(PushPullMessageOut {
+            buffer: self.buffer.into_inner().freeze(),
+        })
+    }
+}
+
+impl From<Limit<BytesMut>> for PushPullMessageEncoder {
+    fn from(buffer: Limit<BytesMut>) -> Self {
+        Self { buffer }
+    }
+}
+
 pub(crate) struct DisseminationMessageEncoder {}
 
 impl DisseminationMessageEncoder {
+    #[allow(clippy::new_ret_no_self)]
     pub(crate) fn new(
         max_size: usize,
     ) -> MessageTypeEncoder<SenderEncoder<SequenceNumberEncoder<NotificationsEncoder>>> {
         MessageTypeEncoder::<SenderEncoder<SequenceNumberEncoder<NotificationsEncoder>>> {
             buffer: BytesMut::with_capacity(max_size).limit(max_size),
+            version: MAX_VERSION,
+            cluster: 0,
             phantom: PhantomData,
         }
     }
@@ -60,11 +102,18 @@ pub(crate) struct PingRequestMessageOut {
     buffer: Bytes,
 }
 
+#[derive(Debug)]
+pub(crate) struct PushPullMessageOut {
+    buffer: Bytes,
+}
+
 #[derive(Debug)]
 pub(crate) struct DisseminationMessageOut {
     buffer: Bytes,
     num_notifications: usize,
     num_broadcast: usize,
+    num_user_messages: usize,
+    num_key_messages: usize,
 }
 
 impl DisseminationMessageOut {
@@ -75,12 +124,22 @@ impl DisseminationMessageOut {
     pub(crate) fn num_broadcast(&self) -> usize {
         self.num_broadcast
     }
+
+    pub(crate) fn num_user_messages(&self) -> usize {
+        self.num_user_messages
+    }
+
+    pub(crate) fn num_key_messages(&self) -> usize {
+        self.num_key_messages
+    }
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum OutgoingMessage {
     DisseminationMessage(DisseminationMessageOut),
     PingRequestMessage(PingRequestMessageOut),
+    PushPullMessage(PushPullMessageOut),
 }
 
 impl OutgoingMessage {
@@ -88,6 +147,7 @@ impl OutgoingMessage {
         match self {
             OutgoingMessage::DisseminationMessage(ref message) => &message.buffer,
             OutgoingMessage::PingRequestMessage(ref message) => &message.buffer,
+            OutgoingMessage::PushPullMessage(ref message) => &message.buffer,
         }
     }
 }
@@ -142,6 +202,8 @@ impl<T> From<Limit<BytesMut>> for TargetEncoder<T> {
 
 pub(crate) struct MessageTypeEncoder<T> {
     buffer: Limit<BytesMut>,
+    version: u8,
+    cluster: u32,
     phantom: std::marker::PhantomData<T>,
 }
 
@@ -149,11 +211,23 @@ impl<T> MessageTypeEncoder<T>
 where
     T: From<Limit<BytesMut>>,
 {
+    /// Sets version of the protocol the message is encoded with, the newest one by default.
+    pub(crate) fn version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets identifier of the group the message belongs to.
+    pub(crate) fn cluster(mut self, cluster: u32) -> Self {
+        self.cluster = cluster;
+        self
+    }
+
     pub(crate) fn message_type(mut self, message_type: MessageType) -> Result<T> {
-        if self.buffer.remaining_mut() < std::mem::size_of::<i32>() {
-            return Err(format_err!("Could not encode message type"));
+        if self.buffer.remaining_mut() < size_of_header(self.version) {
+            return Err(format_err!("Could not encode message header"));
         }
-        self.buffer.put_i32(message_type as i32);
+        encode_header(self.version, self.cluster, message_type, &mut self.buffer);
         Ok(T::from(self.buffer))
     }
 }
@@ -162,11 +236,32 @@ impl<T> From<Limit<BytesMut>> for MessageTypeEncoder<T> {
     fn from(buffer: Limit<BytesMut>) -> Self {
         Self {
             buffer,
+            version: MAX_VERSION,
+            cluster: 0,
             phantom: PhantomData,
         }
     }
 }
 
+/// Size of magic byte, protocol version, group identifier and message type starting every message.
+fn size_of_header(version: u8) -> usize {
+    if version >= CLUSTER_VERSION {
+        7
+    } else {
+        3
+    }
+}
+
+/// Encodes header of the message, older versions of the protocol do not carry the identifier of the group.
+fn encode_header(version: u8, cluster: u32, message_type: MessageType, buffer: &mut Limit<BytesMut>) {
+    buffer.put_u8(MAGIC);
+    buffer.put_u8(version);
+    if version >= CLUSTER_VERSION {
+        buffer.put_u32(cluster);
+    }
+    buffer.put_u8(message_type as u8);
+}
+
 pub(crate) struct SequenceNumberEncoder<T> {
     buffer: Limit<BytesMut>,
     phantom: std::marker::PhantomData<T>,
@@ -212,7 +307,7 @@ impl NotificationsEncoder {
                 if Self::size_of_notification(notification) > self.buffer.remaining_mut() {
                     break;
                 }
-                self.encode_notification(notification)?;
+                encode_notification(notification, &mut self.buffer)?;
                 count += 1;
             }
             self.buffer.get_mut()[count_position] = count;
@@ -222,6 +317,8 @@ impl NotificationsEncoder {
             buffer: self.buffer,
             num_notifications: self.num_notifications,
             num_broadcast: 0,
+            num_user_messages: 0,
+            num_key_messages: 0,
         })
     }
 
@@ -230,36 +327,18 @@ impl NotificationsEncoder {
             buffer: self.buffer.into_inner().freeze(),
             num_notifications: self.num_notifications,
             num_broadcast: 0,
+            num_user_messages: 0,
+            num_key_messages: 0,
         })
     }
 
-    fn encode_notification(&mut self, notification: &Notification) -> Result<()> {
-        if self.buffer.remaining_mut() < 1 {
-            return Err(format_err!("Could not encode notification type"));
-        }
-        match notification {
-            Notification::Alive { member } => {
-                self.buffer.put_u8(0);
-                encode_member(member, &mut self.buffer)?;
-            }
-            Notification::Suspect { member } => {
-                self.buffer.put_u8(1);
-                encode_member(member, &mut self.buffer)?;
-            }
-            Notification::Confirm { member } => {
-                self.buffer.put_u8(2);
-                encode_member(member, &mut self.buffer)?;
-            }
-        }
-        Ok(())
-    }
-
     fn size_of_notification(notification: &Notification) -> usize {
         std::mem::size_of::<u8>()
             + match notification {
-                Notification::Alive { member }
-                | Notification::Suspect { member }
-                | Notification::Confirm { member } => size_of_member(member),
+                Notification::Suspect { member, .. } => size_of_member(member) + std::mem::size_of::<MemberId>(),
+                Notification::Alive { member } | Notification::Confirm { member } | Notification::Leave { member } => {
+                    size_of_member(member)
+                }
             }
     }
 }
@@ -277,6 +356,8 @@ pub(crate) struct BroadcastEncoder {
     buffer: Limit<BytesMut>,
     num_notifications: usize,
     num_broadcast: usize,
+    num_user_messages: usize,
+    num_key_messages: usize,
 }
 
 impl BroadcastEncoder {
@@ -298,15 +379,171 @@ impl BroadcastEncoder {
         Ok(self)
     }
 
+    /// Encodes messages broadcast by the application, has to follow `broadcast`.
+    pub(crate) fn user_messages<'a>(mut self, messages: impl Iterator<Item = &'a UserMessage>) -> Result<Self> {
+        if self.buffer.has_remaining_mut() {
+            let count_position = self.buffer.get_ref().len();
+            self.buffer.put_u8(0);
+            let mut count = 0;
+            for message in messages {
+                if self.buffer.remaining_mut() < size_of_user_message(message) || count == u8::MAX {
+                    break;
+                }
+                encode_user_message(message, &mut self.buffer)?;
+                count += 1;
+            }
+            self.buffer.get_mut()[count_position] = count;
+            self.num_user_messages = count as usize;
+        }
+        Ok(self)
+    }
+
+    /// Encodes requests to change keys and responses to them, has to follow `user_messages`.
+    pub(crate) fn key_messages<'a>(mut self, messages: impl Iterator<Item = &'a KeyMessage>) -> Result<Self> {
+        if self.buffer.has_remaining_mut() {
+            let count_position = self.buffer.get_ref().len();
+            self.buffer.put_u8(0);
+            let mut count = 0;
+            for message in messages {
+                if self.buffer.remaining_mut() < size_of_key_message(message) || count == u8::MAX {
+                    break;
+                }
+                encode_key_message(message, &mut self.buffer)?;
+                count += 1;
+            }
+            self.buffer.get_mut()[count_position] = count;
+            self.num_key_messages = count as usize;
+        }
+        Ok(self)
+    }
+
+    /// Encodes bytes provided by the delegate of the application, has to follow `key_messages`.
+    ///
+    /// `data` is given the number of bytes left in the message, longer data is not encoded.
+    pub(crate) fn delegate_data(mut self, data: impl FnOnce(usize) -> Vec<u8>) -> Result<Self> {
+        if self.buffer.remaining_mut() >= std::mem::size_of::<u16>() {
+            let limit = std::cmp::min(
+                self.buffer.remaining_mut() - std::mem::size_of::<u16>(),
+                u16::MAX as usize,
+            );
+            let data = data(limit);
+            if data.len() <= limit {
+                self.buffer.put_u16(data.len() as u16);
+                self.buffer.put_slice(&data);
+            } else {
+                self.buffer.put_u16(0);
+            }
+        }
+        Ok(self)
+    }
+
     pub(crate) fn encode(self) -> OutgoingMessage {
         OutgoingMessage::DisseminationMessage(DisseminationMessageOut {
             buffer: self.buffer.into_inner().freeze(),
             num_notifications: self.num_notifications,
             num_broadcast: self.num_broadcast,
+            num_user_messages: self.num_user_messages,
+            num_key_messages: self.num_key_messages,
         })
     }
 }
 
+fn encode_notification(notification: &Notification, buffer: &mut Limit<BytesMut>) -> Result<()> {
+    if buffer.remaining_mut() < 1 {
+        return Err(format_err!("Could not encode notification type"));
+    }
+    match notification {
+        Notification::Alive { member } => {
+            buffer.put_u8(0);
+            encode_member(member, buffer)?;
+        }
+        Notification::Suspect { member, from } => {
+            buffer.put_u8(1);
+            encode_member(member, buffer)?;
+            if buffer.remaining_mut() < std::mem::size_of::<MemberId>() {
+                return Err(format_err!("Could not encode suspecting member id"));
+            }
+            buffer.put_slice(from.as_slice());
+        }
+        Notification::Confirm { member } => {
+            buffer.put_u8(2);
+            encode_member(member, buffer)?;
+        }
+        Notification::Leave { member } => {
+            buffer.put_u8(3);
+            encode_member(member, buffer)?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_user_message(message: &UserMessage, buffer: &mut Limit<BytesMut>) -> Result<()> {
+    if buffer.remaining_mut() < size_of_user_message(message) {
+        return Err(format_err!("Could not encode user message"));
+    }
+    buffer.put_slice(message.origin.as_slice());
+    buffer.put_u64(message.sequence_number);
+    buffer.put_u16(message.payload.len() as u16);
+    buffer.put_slice(&message.payload);
+    Ok(())
+}
+
+fn size_of_user_message(message: &UserMessage) -> usize {
+    size_of_vals!(message.origin, message.sequence_number) + std::mem::size_of::<u16>() + message.payload.len()
+}
+
+fn encode_key_message(message: &KeyMessage, buffer: &mut Limit<BytesMut>) -> Result<()> {
+    if buffer.remaining_mut() < size_of_key_message(message) {
+        return Err(format_err!("Could not encode key message"));
+    }
+    match message {
+        KeyMessage::Request {
+            origin,
+            sequence_number,
+            operation,
+            key,
+        } => {
+            buffer.put_u8(0);
+            buffer.put_slice(origin.as_slice());
+            buffer.put_u64(*sequence_number);
+            buffer.put_u8(*operation as u8);
+            buffer.put_u8(key.len() as u8);
+            buffer.put_slice(key);
+        }
+        KeyMessage::Response {
+            origin,
+            sequence_number,
+            member,
+            result,
+        } => {
+            buffer.put_u8(1);
+            buffer.put_slice(origin.as_slice());
+            buffer.put_u64(*sequence_number);
+            buffer.put_slice(member.as_slice());
+            buffer.put_u8(key_management::encode_result(result));
+        }
+    }
+    Ok(())
+}
+
+fn size_of_key_message(message: &KeyMessage) -> usize {
+    std::mem::size_of::<u8>()
+        + match message {
+            KeyMessage::Request {
+                origin,
+                sequence_number,
+                key,
+                ..
+            } => size_of_vals!(*origin, *sequence_number) + 2 * std::mem::size_of::<u8>() + key.len(),
+            KeyMessage::Response {
+                origin,
+                sequence_number,
+                member,
+                ..
+            } => size_of_vals!(*origin, *sequence_number, *member) + std::mem::size_of::<u8>(),
+        }
+}
+
 fn encode_member(member: &Member, buffer: &mut Limit<BytesMut>) -> Result<()> {
     if buffer.remaining_mut() < size_of_member(member) {
         return Err(format_err!("Could not encode member"));
@@ -320,11 +557,16 @@ fn encode_member(member: &Member, buffer: &mut Limit<BytesMut>) -> Result<()> {
             buffer.put_slice(&address.ip().octets());
             buffer.put_u16(address.port());
         }
-        SocketAddr::V6(_) => {
-            buffer.get_mut()[position] = 1u8 << 7;
-            todo!();
+        SocketAddr::V6(address) => {
+            buffer.get_mut()[position] = 1u8;
+            buffer.put_slice(&address.ip().octets());
+            buffer.put_u16(address.port());
         }
     };
+    buffer.put_u8(member.metadata.len() as u8);
+    buffer.put_slice(&member.metadata);
+    buffer.put_u8(member.min_version);
+    buffer.put_u8(member.max_version);
     Ok(())
 }
 
@@ -333,23 +575,16 @@ fn size_of_member(member: &Member) -> usize {
         + size_of_vals!(member.id, member.incarnation)
         + match member.address {
             SocketAddr::V4(address) => size_of_vals!(address.ip().octets(), address.port()),
-            SocketAddr::V6(_) => todo!(),
+            SocketAddr::V6(address) => size_of_vals!(address.ip().octets(), address.port()),
         }
+        + std::mem::size_of::<u8>()
+        + member.metadata.len()
+        + size_of_vals!(member.min_version, member.max_version)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::str::FromStr;
-
-    struct NullEncoder {
-        buffer: Limit<BytesMut>,
-    }
-    impl From<Limit<BytesMut>> for NullEncoder {
-        fn from(buffer: Limit<BytesMut>) -> Self {
-            Self { buffer }
-        }
-    }
 
     mod notifications {
         use super::*;
@@ -357,7 +592,7 @@ mod test {
 
         #[test]
         fn skip_when_empty_buffer() {
-            let notifications = vec![Notification::Alive {
+            let notifications = [Notification::Alive {
                 member: create_member(0),
             }];
             let encoder = NotificationsEncoder::from(BytesMut::new().limit(0));
@@ -366,7 +601,7 @@ mod test {
 
         #[test]
         fn dont_overflow_buffer() {
-            let notifications = vec![Notification::Alive {
+            let notifications = [Notification::Alive {
                 member: create_member(0),
             }];
             let encoder = NotificationsEncoder::from(BytesMut::new().limit(1));
@@ -376,7 +611,7 @@ mod test {
 
         #[test]
         fn encode_notification_when_space_in_buffer() {
-            let notifications = vec![Notification::Alive {
+            let notifications = [Notification::Alive {
                 member: create_member(0),
             }];
             // Adding `1` as the number of notifications is stored in a single byte.
@@ -398,6 +633,8 @@ mod test {
                 buffer: BytesMut::new().limit(0),
                 num_notifications: 0,
                 num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
             };
             encoder.broadcast(create_members(1).iter()).unwrap();
         }
@@ -408,6 +645,8 @@ mod test {
                 buffer: BytesMut::new().limit(1),
                 num_notifications: 0,
                 num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
             };
             let encoder = encoder.broadcast(create_members(1).iter()).unwrap();
             assert_eq!(encoder.num_broadcast, 0);
@@ -420,9 +659,150 @@ mod test {
                 buffer: BytesMut::new().limit(1 + size_of_member(&members[0])),
                 num_notifications: 0,
                 num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
             };
             let encoder = encoder.broadcast(members.iter()).unwrap();
             assert_eq!(encoder.num_broadcast, 1);
         }
     }
+
+    mod user_messages {
+        use super::*;
+        use crate::ututils::*;
+
+        fn create_user_message(payload: &'static [u8]) -> UserMessage {
+            UserMessage {
+                origin: create_member(0).id,
+                sequence_number: 0,
+                payload: Bytes::from_static(payload),
+            }
+        }
+
+        #[test]
+        fn skip_when_empty_buffer() {
+            let encoder = BroadcastEncoder {
+                buffer: BytesMut::new().limit(0),
+                num_notifications: 0,
+                num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
+            };
+            encoder.user_messages([create_user_message(b"foo")].iter()).unwrap();
+        }
+
+        #[test]
+        fn dont_overflow_buffer() {
+            let message = create_user_message(b"foo");
+            let encoder = BroadcastEncoder {
+                buffer: BytesMut::new().limit(size_of_user_message(&message)),
+                num_notifications: 0,
+                num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
+            };
+            let encoder = encoder.user_messages([message].iter()).unwrap();
+            assert_eq!(encoder.num_user_messages, 0);
+        }
+
+        #[test]
+        fn encode_user_message_when_space_in_buffer() {
+            let message = create_user_message(b"foo");
+            let encoder = BroadcastEncoder {
+                buffer: BytesMut::new().limit(1 + size_of_user_message(&message)),
+                num_notifications: 0,
+                num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
+            };
+            let encoder = encoder.user_messages([message].iter()).unwrap();
+            assert_eq!(encoder.num_user_messages, 1);
+        }
+    }
+
+    mod key_messages {
+        use super::*;
+        use crate::key_management::KeyOperation;
+        use crate::ututils::*;
+
+        fn create_key_messages() -> Vec<KeyMessage> {
+            vec![
+                KeyMessage::Request {
+                    origin: create_member(0).id,
+                    sequence_number: 0,
+                    operation: KeyOperation::Install,
+                    key: vec![1u8; 32],
+                },
+                KeyMessage::Response {
+                    origin: create_member(0).id,
+                    sequence_number: 0,
+                    member: create_member(1).id,
+                    result: Ok(()),
+                },
+            ]
+        }
+
+        fn create_encoder(size: usize) -> BroadcastEncoder {
+            BroadcastEncoder {
+                buffer: BytesMut::new().limit(size),
+                num_notifications: 0,
+                num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
+            }
+        }
+
+        #[test]
+        fn dont_overflow_buffer() {
+            for message in create_key_messages() {
+                let encoder = create_encoder(size_of_key_message(&message));
+                let encoder = encoder.key_messages([message].iter()).unwrap();
+                assert_eq!(encoder.num_key_messages, 0);
+            }
+        }
+
+        #[test]
+        fn encode_key_messages_when_space_in_buffer() {
+            let messages = create_key_messages();
+            let size = 1 + messages.iter().map(size_of_key_message).sum::<usize>();
+            let encoder = create_encoder(size).key_messages(messages.iter()).unwrap();
+            assert_eq!(encoder.num_key_messages, 2);
+            assert_eq!(encoder.buffer.get_ref().len(), size);
+        }
+    }
+
+    mod delegate_data {
+        use super::*;
+
+        fn create_encoder(size: usize) -> BroadcastEncoder {
+            BroadcastEncoder {
+                buffer: BytesMut::new().limit(size),
+                num_notifications: 0,
+                num_broadcast: 0,
+                num_user_messages: 0,
+                num_key_messages: 0,
+            }
+        }
+
+        #[test]
+        fn skip_when_empty_buffer() {
+            let encoder = create_encoder(1)
+                .delegate_data(|_| panic!("No space for delegate data"))
+                .unwrap();
+            assert!(encoder.buffer.get_ref().is_empty());
+        }
+
+        #[test]
+        fn dont_overflow_buffer() {
+            let encoder = create_encoder(6).delegate_data(|_| b"delegate".to_vec()).unwrap();
+            assert_eq!(encoder.buffer.get_ref().as_ref(), &[0, 0]);
+        }
+
+        #[test]
+        fn encode_delegate_data_up_to_limit() {
+            let encoder = create_encoder(10).delegate_data(|limit| vec![1u8; limit]).unwrap();
+            assert_eq!(encoder.buffer.get_ref().len(), 10);
+            assert_eq!(&encoder.buffer.get_ref()[..2], &[0, 8]);
+        }
+    }
 }
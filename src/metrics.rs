@@ -0,0 +1,14 @@
+#![deny(missing_docs)]
+
+/// Counters of events observed by a member since it started.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Number of messages dropped because they were not authenticated with the shared secret key.
+    pub unauthenticated_messages: u64,
+
+    /// Number of messages dropped because none of the installed keys could decrypt them.
+    pub undecryptable_messages: u64,
+
+    /// Number of messages dropped because they were sent by members of a group with a different cluster name.
+    pub foreign_cluster_messages: u64,
+}
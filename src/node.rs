@@ -1,11 +1,24 @@
 #![deny(missing_docs)]
 
+use crate::delegate::Delegate;
+use crate::error::{JoinError, KeyringError};
+use crate::event::MembershipEvent;
+use crate::key_management::{KeyOperation, KeyReport};
+use crate::keyring::Keyring;
+use crate::member::MAX_METADATA_SIZE;
+use crate::metrics::Metrics;
 use crate::result::Result;
-use crate::sync_node::{ChannelMessage, SyncNode};
+#[cfg(feature = "tokio")]
+use crate::sync_node::Call;
+use crate::sync_node::{ChannelMessage, Joined, SyncNode};
+use crate::transport::Transport;
+use crate::user_message::MAX_USER_MESSAGE_SIZE;
 use crate::ProtocolConfig;
 use failure::format_err;
 use mio_extras::channel::Sender;
 use std::net::SocketAddr;
+use std::sync::mpsc::{RecvTimeoutError, SyncSender};
+use std::time::Duration;
 
 /// Runs the gossip protocol on an internal thread.
 pub struct Node {
@@ -14,6 +27,10 @@ pub struct Node {
     sender: Option<Sender<ChannelMessage>>,
     handle: Option<std::thread::JoinHandle<Result<()>>>,
     logger: Option<slog::Logger>,
+    metadata: Option<Vec<u8>>,
+    keyring: Keyring,
+    delegate: Option<Box<dyn Delegate>>,
+    transport: Option<Box<dyn Transport>>,
 }
 
 impl Node {
@@ -25,6 +42,25 @@ impl Node {
             sender: None,
             handle: None,
             logger: None,
+            metadata: None,
+            keyring: Keyring::default(),
+            delegate: None,
+            transport: None,
+        }
+    }
+
+    /// Creates new instance exchanging messages with other members through `transport` instead of UDP socket.
+    ///
+    /// Other members know this one by `bind_address`, the transport has to deliver datagrams sent to it. Nothing is
+    /// bound to the address on the host, so push-pull synchronisation over TCP is disabled for this member.
+    pub fn with_transport(
+        bind_address: SocketAddr,
+        config: ProtocolConfig,
+        transport: impl Transport + 'static,
+    ) -> Self {
+        Node {
+            transport: Some(Box::new(transport)),
+            ..Node::new(bind_address, config)
         }
     }
 
@@ -37,6 +73,119 @@ impl Node {
         self.logger = Some(logger);
     }
 
+    /// Sets hooks of the application into the protocol.
+    pub fn set_delegate(&mut self, delegate: impl Delegate + 'static) {
+        assert!(
+            self.handle.is_none(),
+            "Delegate can only be set before starting the node."
+        );
+        self.delegate = Some(Box::new(delegate));
+    }
+
+    /// Sets application defined metadata of this member, e.g. its role or version.
+    ///
+    /// Metadata can be changed at any time, other members learn about the change through the gossip. It can not be
+    /// larger than 128 bytes.
+    pub fn set_metadata(&mut self, metadata: Vec<u8>) -> Result<()> {
+        if metadata.len() > MAX_METADATA_SIZE {
+            return Err(format_err!(
+                "Metadata of {} bytes exceeds the limit of {} bytes",
+                metadata.len(),
+                MAX_METADATA_SIZE
+            ));
+        }
+        match self.sender {
+            None => {
+                self.metadata = Some(metadata);
+                Ok(())
+            }
+            Some(ref sender) => sender
+                .send(ChannelMessage::SetMetadata(metadata))
+                .map_err(|e| format_err!("Failed to set metadata: {:?}", e)),
+        }
+    }
+
+    /// Installs `key` decrypting messages from other members, the first installed key also encrypts them.
+    ///
+    /// Messages are encrypted with AES-GCM once a key is installed, so all members of the group have to install it
+    /// before they start. The key has to be 16 or 32 bytes long, for AES-128 or AES-256 respectively. Keys can be
+    /// rotated without downtime by installing a new key on all members, using it and finally removing the old one.
+    pub fn install_key(&mut self, key: Vec<u8>) -> Result<()> {
+        match self.sender {
+            None => Ok(self.keyring.install(key)?),
+            Some(_) => self.change_keyring(|sender| ChannelMessage::InstallKey(key, sender)),
+        }
+    }
+
+    /// Encrypts messages with already installed `key`.
+    pub fn use_key(&mut self, key: &[u8]) -> Result<()> {
+        match self.sender {
+            None => Ok(self.keyring.use_key(key)?),
+            Some(_) => self.change_keyring(|sender| ChannelMessage::UseKey(key.to_vec(), sender)),
+        }
+    }
+
+    /// Removes installed `key`, messages encrypted with it are dropped from now on.
+    ///
+    /// The key encrypting messages can not be removed.
+    pub fn remove_key(&mut self, key: &[u8]) -> Result<()> {
+        match self.sender {
+            None => Ok(self.keyring.remove(key)?),
+            Some(_) => self.change_keyring(|sender| ChannelMessage::RemoveKey(key.to_vec(), sender)),
+        }
+    }
+
+    /// Installs `key` on all members of the group, see [install_key](#method.install_key).
+    ///
+    /// The request is disseminated through the gossip, encrypted with the current key, and the report of members
+    /// that answered is returned once all known members answered or `timeout` passes. Fails with
+    /// [KeyringError](enum.KeyringError.html) if messages are not encrypted or the key is invalid.
+    pub fn install_group_key(&self, key: Vec<u8>, timeout: Duration) -> Result<KeyReport> {
+        self.change_group_keys(KeyOperation::Install, key, timeout)
+    }
+
+    /// Makes all members of the group encrypt messages with already installed `key`.
+    ///
+    /// Should follow successful [install_group_key](#method.install_group_key), otherwise members that do not know
+    /// the key will be unable to decrypt the messages.
+    pub fn use_group_key(&self, key: &[u8], timeout: Duration) -> Result<KeyReport> {
+        self.change_group_keys(KeyOperation::Use, key.to_vec(), timeout)
+    }
+
+    /// Removes `key` from all members of the group.
+    pub fn remove_group_key(&self, key: &[u8], timeout: Duration) -> Result<KeyReport> {
+        self.change_group_keys(KeyOperation::Remove, key.to_vec(), timeout)
+    }
+
+    fn change_group_keys(&self, operation: KeyOperation, key: Vec<u8>, timeout: Duration) -> Result<KeyReport> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::ChangeGroupKeys(operation, key, timeout, sender))
+            .map_err(|e| format_err!("Failed to ask for changing keys: {:?}", e))?;
+        Ok(receiver
+            .recv()
+            .map_err(|e| format_err!("Failed to change keys: {:?}", e))??)
+    }
+
+    fn change_keyring(
+        &self,
+        message: impl FnOnce(SyncSender<std::result::Result<(), KeyringError>>) -> ChannelMessage,
+    ) -> Result<()> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(message(sender))
+            .map_err(|e| format_err!("Failed to ask for changing keys: {:?}", e))?;
+        Ok(receiver
+            .recv()
+            .map_err(|e| format_err!("Failed to change keys: {:?}", e))??)
+    }
+
     /// Returns bind address of this member.
     pub fn bind_address(&self) -> SocketAddr {
         self.bind_address
@@ -46,52 +195,125 @@ impl Node {
     ///
     /// Member might not be instantly spotted by all other members of the group.
     pub fn join(&mut self, member: SocketAddr) -> Result<()> {
-        assert_ne!(member, self.bind_address, "Can't join yourself");
-        assert!(self.handle.is_none(), "You have already started");
+        self.join_any(&[member])
+    }
 
-        let (mut sync_node, sender) = SyncNode::new(self.bind_address, self.config.take().unwrap());
-        if let Some(logger) = self.logger.take() {
-            sync_node.set_logger(logger)
+    /// Joins the group through any of the `seeds` which have to already belong to the group.
+    ///
+    /// Seeds are tried in random order, each round of attempts is delayed exponentially starting with
+    /// `join_retry_timeout`. If none of them answers in `join_max_attempts` rounds, the member stops and
+    /// [JoinError](enum.JoinError.html) is returned by `stop`. Fails with `JoinError::NoSeeds` if `seeds` is empty.
+    pub fn join_any(&mut self, seeds: &[SocketAddr]) -> Result<()> {
+        self.spawn_join(seeds, None)
+    }
+
+    /// Joins the group through any of the `seeds` and waits until the first of them answers.
+    ///
+    /// Returns members of the group known right after joining, once the state of the group has been synchronised
+    /// with the seed. Fails with [JoinError](enum.JoinError.html) if none of the seeds answers or `timeout` passes,
+    /// in the latter case the member keeps trying to join until it is stopped.
+    pub fn join_and_wait(&mut self, seeds: &[SocketAddr], timeout: Duration) -> Result<Vec<SocketAddr>> {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.spawn_join(
+            seeds,
+            Some(Box::new(move |members| {
+                // Nobody waiting for the result is not an error.
+                let _ = sender.send(members);
+            })),
+        )?;
+        match receiver.recv_timeout(timeout) {
+            Ok(members) => Ok(members),
+            Err(RecvTimeoutError::Timeout) => Err(JoinError::Timeout.into()),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.wait()?;
+                Err(format_err!("Member stopped before joining the group"))
+            }
         }
-        self.sender = Some(sender);
-        self.handle = Some(
-            std::thread::Builder::new()
-                .name("membership".to_string())
-                .spawn(move || sync_node.join(member))?,
-        );
-        Ok(())
+    }
+
+    fn spawn_join(&mut self, seeds: &[SocketAddr], joined: Option<Joined>) -> Result<()> {
+        if seeds.is_empty() {
+            return Err(JoinError::NoSeeds.into());
+        }
+        assert!(!seeds.contains(&self.bind_address), "Can't join yourself");
+        let seeds = seeds.to_vec();
+        self.spawn(move |mut sync_node| sync_node.join(seeds, joined))
     }
 
     /// Starts new group.
+    ///
+    /// Fails with [ConfigError](enum.ConfigError.html) if the configuration is not valid.
     pub fn start(&mut self) -> Result<()> {
+        self.spawn(|mut sync_node| sync_node.start())
+    }
+
+    /// Runs `run` with the protocol set up according to this member on a new thread.
+    pub(crate) fn spawn(&mut self, run: impl FnOnce(SyncNode) -> Result<()> + Send + 'static) -> Result<()> {
         assert!(self.handle.is_none(), "You have already started");
+        self.config.as_ref().unwrap().validate()?;
 
         let (mut sync_node, sender) = SyncNode::new(self.bind_address, self.config.take().unwrap());
         if let Some(logger) = self.logger.take() {
             sync_node.set_logger(logger)
         }
+        if let Some(metadata) = self.metadata.take() {
+            sync_node.set_metadata(metadata)
+        }
+        sync_node.set_keyring(std::mem::take(&mut self.keyring));
+        if let Some(delegate) = self.delegate.take() {
+            sync_node.set_delegate(delegate);
+        }
+        if let Some(transport) = self.transport.take() {
+            sync_node.set_transport(transport);
+        }
         self.sender = Some(sender);
         self.handle = Some(
             std::thread::Builder::new()
                 .name("membership".to_string())
-                .spawn(move || sync_node.start())?,
+                .spawn(move || run(sync_node))?,
         );
         Ok(())
     }
 
+    /// Runs `call` on the thread of the protocol.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn call(&self, call: impl FnOnce(&mut SyncNode) + Send + 'static) -> Result<()> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::Call(Call(Box::new(call))))
+            .map_err(|e| format_err!("Failed to call the protocol: {:?}", e))
+    }
+
+    /// Forgets the thread of the protocol without waiting for it, its result has to be delivered by `run`.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn detach(&mut self) {
+        assert!(self.handle.take().is_some(), "You have not joined yet");
+    }
+
     /// Stops this member, removing it from the group.
     ///
-    /// Member does not broadcast that its quiting (at least not yet), thus it may still be observed by others
-    /// as alive, at least for a short period of time.
+    /// Before stopping, the member spends `leave_timeout` spreading the information that it is leaving,
+    /// so that others can remove it without suspecting it first.
     pub fn stop(&mut self) -> Result<()> {
+        // Protocol which has already stopped on its own, e.g. failing to join the group, cannot be asked to stop,
+        // its own error is more useful then.
+        let requested = self.request_stop();
+        self.wait()?;
+        requested
+    }
+
+    /// Asks the protocol to stop, without waiting for it.
+    pub(crate) fn request_stop(&self) -> Result<()> {
         assert!(self.handle.is_some(), "You have not joined yet");
 
         self.sender
             .as_ref()
             .unwrap()
             .send(ChannelMessage::Stop)
-            .map_err(|e| format_err!("Failed to stop message: {:?}", e))?;
-        self.wait()
+            .map_err(|e| format_err!("Failed to stop message: {:?}", e))
     }
 
     /// Returns all alive members of the group this member knows about.
@@ -111,6 +333,104 @@ impl Node {
             .map_err(|e| format_err!("Failed to get members: {:?}", e))
     }
 
+    /// Returns all alive members of the group this member knows about, together with their metadata.
+    pub fn get_members_with_metadata(&self) -> Result<Vec<(SocketAddr, Vec<u8>)>> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::GetMembersWithMetadata(sender))
+            .map_err(|e| format_err!("Failed to ask for members: {:?}", e))?;
+        receiver
+            .recv()
+            .map_err(|e| format_err!("Failed to get members: {:?}", e))
+    }
+
+    /// Returns number of members confirmed dead or gone that are still remembered.
+    ///
+    /// They are forgotten after `dead_member_retention`.
+    pub fn get_dead_members_count(&self) -> Result<usize> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::GetDeadMembersCount(sender))
+            .map_err(|e| format_err!("Failed to ask for dead members: {:?}", e))?;
+        receiver
+            .recv()
+            .map_err(|e| format_err!("Failed to get dead members: {:?}", e))
+    }
+
+    /// Broadcasts an opaque `payload` to all members of the group.
+    ///
+    /// The payload is piggybacked on the protocol messages, so it is delivered eventually and at most once to every
+    /// member, but not necessarily to all of them. It can not be larger than 512 bytes.
+    pub fn broadcast(&self, payload: Vec<u8>) -> Result<()> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        if payload.len() > MAX_USER_MESSAGE_SIZE {
+            return Err(format_err!(
+                "Payload of {} bytes exceeds the limit of {} bytes",
+                payload.len(),
+                MAX_USER_MESSAGE_SIZE
+            ));
+        }
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::Broadcast(payload))
+            .map_err(|e| format_err!("Failed to broadcast: {:?}", e))
+    }
+
+    /// Returns a receiver of payloads broadcast by other members of the group.
+    ///
+    /// Only payloads received after subscribing are delivered.
+    pub fn subscribe_broadcasts(&self) -> Result<std::sync::mpsc::Receiver<Vec<u8>>> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::SubscribeBroadcasts(sender))
+            .map_err(|e| format_err!("Failed to subscribe to broadcasts: {:?}", e))?;
+        Ok(receiver)
+    }
+
+    /// Returns receiver of changes of the group observed by this member, as soon as they happen.
+    ///
+    /// Events are sent until the receiver is dropped.
+    pub fn subscribe(&self) -> Result<std::sync::mpsc::Receiver<MembershipEvent>> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::Subscribe(sender))
+            .map_err(|e| format_err!("Failed to subscribe to membership events: {:?}", e))?;
+        Ok(receiver)
+    }
+
+    /// Returns counters of events observed by this member, e.g. dropped unauthenticated messages.
+    pub fn get_metrics(&self) -> Result<Metrics> {
+        assert!(self.handle.is_some(), "First you have to join");
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        self.sender
+            .as_ref()
+            .unwrap()
+            .send(ChannelMessage::GetMetrics(sender))
+            .map_err(|e| format_err!("Failed to ask for metrics: {:?}", e))?;
+        receiver
+            .recv()
+            .map_err(|e| format_err!("Failed to get metrics: {:?}", e))
+    }
+
     #[doc(hidden)]
     /// Waits for the member to finish.
     pub fn wait(&mut self) -> Result<()> {
@@ -1,11 +1,12 @@
-use crate::member::Member;
+use crate::member::{Member, MemberId};
 use failure::_core::cmp::Ordering;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Notification {
     Alive { member: Member },
-    Suspect { member: Member },
+    Suspect { member: Member, from: MemberId },
     Confirm { member: Member },
+    Leave { member: Member },
 }
 
 impl PartialEq for Notification {
@@ -24,19 +25,21 @@ impl PartialOrd for Notification {
                 Notification::Alive { member: other_member } => {
                     member.incarnation.partial_cmp(&other_member.incarnation)
                 }
-                Notification::Suspect { member: other_member } => {
+                Notification::Suspect {
+                    member: other_member, ..
+                } => {
                     if other_member.incarnation >= member.incarnation {
                         Some(Ordering::Less)
                     } else {
                         Some(Ordering::Greater)
                     }
                 }
-                Notification::Confirm { .. } => Some(Ordering::Less),
+                Notification::Confirm { .. } | Notification::Leave { .. } => Some(Ordering::Less),
             },
-            Notification::Suspect { member } => match other {
-                Notification::Suspect { member: other_member } => {
-                    member.incarnation.partial_cmp(&other_member.incarnation)
-                }
+            Notification::Suspect { member, .. } => match other {
+                Notification::Suspect {
+                    member: other_member, ..
+                } => member.incarnation.partial_cmp(&other_member.incarnation),
                 Notification::Alive { member: other_member } => {
                     if member.incarnation >= other_member.incarnation {
                         Some(Ordering::Greater)
@@ -44,11 +47,12 @@ impl PartialOrd for Notification {
                         Some(Ordering::Less)
                     }
                 }
-                Notification::Confirm { .. } => Some(Ordering::Less),
+                Notification::Confirm { .. } | Notification::Leave { .. } => Some(Ordering::Less),
             },
-            Notification::Confirm { .. } => match other {
+            // Both Confirm and Leave are final, the member will not come back with the same ID.
+            Notification::Confirm { .. } | Notification::Leave { .. } => match other {
                 Notification::Alive { .. } | Notification::Suspect { .. } => Some(Ordering::Greater),
-                Notification::Confirm { .. } => Some(Ordering::Equal),
+                Notification::Confirm { .. } | Notification::Leave { .. } => Some(Ordering::Equal),
             },
         }
     }
@@ -57,24 +61,21 @@ impl PartialOrd for Notification {
 impl Notification {
     pub(crate) fn member(&self) -> &Member {
         match self {
-            Notification::Alive { member } | Notification::Confirm { member } | Notification::Suspect { member } => {
-                member
-            }
+            Notification::Alive { member }
+            | Notification::Confirm { member }
+            | Notification::Suspect { member, .. }
+            | Notification::Leave { member } => member,
         }
     }
 
     pub(crate) fn is_suspect(&self) -> bool {
-        match *self {
-            Notification::Suspect { .. } => true,
-            _ => false,
-        }
+        matches!(*self, Notification::Suspect { .. })
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::member::MemberId;
     use std::convert::TryFrom;
     use std::net::SocketAddr;
     use std::str::FromStr;
@@ -89,6 +90,9 @@ mod test {
                 address,
                 incarnation: 1,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         let suspect = Notification::Suspect {
@@ -96,13 +100,20 @@ mod test {
                 address,
                 incarnation: 1,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
+            from: member_id,
         };
         let confirm = Notification::Confirm {
             member: Member {
                 address,
                 incarnation: 1,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         assert!(alive < suspect);
@@ -121,6 +132,9 @@ mod test {
                 address,
                 incarnation: 3,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         let suspect = Notification::Suspect {
@@ -128,21 +142,32 @@ mod test {
                 address,
                 incarnation: 2,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
+            from: member_id,
         };
         let confirm = Notification::Confirm {
             member: Member {
                 address,
                 incarnation: 1,
                 id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         let suspect_with_higher_incarnation = Notification::Suspect {
             member: Member {
                 address,
                 incarnation: 3,
-                id: member_id
-            }
+                id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
+            },
+            from: member_id,
         };
         assert!(alive > suspect);
         assert!(suspect < confirm);
@@ -152,6 +177,58 @@ mod test {
         assert!(alive < suspect_with_higher_incarnation);
     }
 
+    #[test]
+    fn test_compare_leave() {
+        let address = SocketAddr::from_str("127.0.0.1:1234").unwrap();
+        let member_id = MemberId::try_from([0u8; 20].as_ref()).unwrap();
+
+        let alive = Notification::Alive {
+            member: Member {
+                address,
+                incarnation: 2,
+                id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
+            },
+        };
+        let suspect = Notification::Suspect {
+            member: Member {
+                address,
+                incarnation: 2,
+                id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
+            },
+            from: member_id,
+        };
+        let confirm = Notification::Confirm {
+            member: Member {
+                address,
+                incarnation: 1,
+                id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
+            },
+        };
+        let leave = Notification::Leave {
+            member: Member {
+                address,
+                incarnation: 1,
+                id: member_id,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
+            },
+        };
+        assert!(alive < leave);
+        assert!(suspect < leave);
+        assert!(leave > alive);
+        assert_eq!(leave.partial_cmp(&confirm), Some(Ordering::Equal));
+    }
+
     #[test]
     fn test_compare_different_members() {
         let address = SocketAddr::from_str("127.0.0.1:1234").unwrap();
@@ -164,6 +241,9 @@ mod test {
                 address,
                 incarnation: 1,
                 id: member_id1,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         let suspect = Notification::Suspect {
@@ -171,13 +251,20 @@ mod test {
                 address,
                 incarnation: 1,
                 id: member_id2,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
+            from: member_id1,
         };
         let confirm = Notification::Confirm {
             member: Member {
                 address,
                 incarnation: 1,
                 id: member_id3,
+                metadata: Vec::new(),
+                min_version: 1,
+                max_version: 1,
             },
         };
         assert_eq!(alive.partial_cmp(&suspect), None);
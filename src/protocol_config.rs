@@ -1,14 +1,19 @@
 #![deny(missing_docs)]
 
+use crate::error::ConfigError;
+use std::fmt;
+use std::time::Duration;
+
 /// Configuration for the membership protocol.
+#[derive(Clone)]
 pub struct ProtocolConfig {
-    /// Number of seconds between checking new member.
-    pub protocol_period: u64,
+    /// Time between checking new member.
+    pub protocol_period: Duration,
 
-    /// Number of seconds to wait for response from a peer.
+    /// Time to wait for response from a peer.
     ///
-    /// Must be significantly (e.g. four times) smaller than `protocol_period`.
-    pub ack_timeout: u8,
+    /// Must be non-zero and smaller than `protocol_period`, indirect probes only get the rest of the period.
+    pub ack_timeout: Duration,
 
     /// Maximum number of members selected for indirect probing.
     ///
@@ -16,25 +21,404 @@ pub struct ProtocolConfig {
     /// the probed member.
     pub num_indirect: u8,
 
-    /// Number of seconds to keep member as suspected before removing it.
-    pub suspect_timeout: u64,
+    /// Minimal time to keep member as suspected before removing it.
+    ///
+    /// The value is scaled by logarithm of the group size, so larger groups keep suspected members longer.
+    pub suspect_timeout: Duration,
+
+    /// Multiplier of `suspect_timeout` giving the maximal time a member is kept suspected.
+    ///
+    /// A suspicion starts with the maximal timeout which shrinks towards `suspect_timeout` as other members
+    /// independently suspect the same member. Member no one else suspects is removed only after the maximal
+    /// timeout, which is 15s by default in groups of fewer than 10 members.
+    pub suspect_max_timeout_multiplier: u32,
+
+    /// Number of independent confirmations after which a suspicion times out after `suspect_timeout`.
+    pub suspect_confirmations: u32,
+
+    /// Time until failed join request will be retried.
+    ///
+    /// The delay doubles with every attempt to reach the seed members.
+    pub join_retry_timeout: Duration,
+
+    /// Number of times all seed members are tried before joining fails.
+    pub join_max_attempts: u32,
+
+    /// Multiplier λ of the number of times a notification is disseminated to other group members.
+    ///
+    /// Following SWIM, a notification is piggybacked `λ·log(n)` times, where `n` is the size of the group. The same
+    /// limit applies to broadcasting alive members and payloads of the application.
+    pub dissemination_multiplier: u64,
+
+    /// Upper bound of the multiplier applied to `protocol_period` and `ack_timeout` when this member is unhealthy.
+    ///
+    /// Following Lifeguard extensions to SWIM, a member that misses acks or is suspected by others assumes it may
+    /// be the source of problems and slows down its failure detection. Setting it to `1` disables the mechanism.
+    pub max_local_health_multiplier: u32,
+
+    /// Time between synchronising entire state of the group with a random member over TCP.
+    ///
+    /// The state is always synchronised with the member that is used to join the group, zero disables only
+    /// periodic synchronisation.
+    pub push_pull_interval: Duration,
+
+    /// Time a stopping member keeps spreading the information that it is leaving the group.
+    pub leave_timeout: Duration,
+
+    /// Time a member confirmed dead or gone is remembered, so it is not added back by stale gossip.
+    ///
+    /// Should be long enough for information about the member to stop being disseminated.
+    pub dead_member_retention: Duration,
 
-    /// Number of seconds until failed join request will be retried.
-    pub join_retry_timeout: u64,
+    /// Secret shared by all members of the group, authenticating every message they exchange.
+    ///
+    /// Messages are signed with HMAC-SHA256 of the key, those with a missing or invalid signature are dropped.
+    /// Without the key messages are not authenticated, so anyone able to reach the member can affect the group.
+    pub secret_key: Option<Vec<u8>>,
+
+    /// Name of the group, carried as a hash in every message.
+    ///
+    /// Messages of groups with a different name are dropped, so independent groups sharing a network do not merge
+    /// even if they are given each other's members as seeds. Messages of members speaking version 1 of the protocol
+    /// do not carry the name, such members only belong to the unnamed group.
+    pub cluster_name: String,
+}
 
-    /// Number of times a notification is disseminated to other group members.
-    pub notification_dissemination_times: u64,
+impl fmt::Debug for ProtocolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProtocolConfig")
+            .field("protocol_period", &self.protocol_period)
+            .field("ack_timeout", &self.ack_timeout)
+            .field("num_indirect", &self.num_indirect)
+            .field("suspect_timeout", &self.suspect_timeout)
+            .field("suspect_max_timeout_multiplier", &self.suspect_max_timeout_multiplier)
+            .field("suspect_confirmations", &self.suspect_confirmations)
+            .field("join_retry_timeout", &self.join_retry_timeout)
+            .field("join_max_attempts", &self.join_max_attempts)
+            .field("dissemination_multiplier", &self.dissemination_multiplier)
+            .field("max_local_health_multiplier", &self.max_local_health_multiplier)
+            .field("push_pull_interval", &self.push_pull_interval)
+            .field("leave_timeout", &self.leave_timeout)
+            .field("dead_member_retention", &self.dead_member_retention)
+            .field("secret_key", &self.secret_key.as_ref().map(|_| "<redacted>"))
+            .field("cluster_name", &self.cluster_name)
+            .finish()
+    }
+}
+
+impl ProtocolConfig {
+    /// Returns builder of configuration validated before it is built, starting with the default one.
+    pub fn builder() -> ProtocolConfigBuilder {
+        ProtocolConfigBuilder::from(ProtocolConfig::default())
+    }
+
+    /// Configuration for members in a local network, similar to memberlist's `DefaultLANConfig`.
+    pub fn lan() -> Self {
+        ProtocolConfig {
+            protocol_period: Duration::from_secs(1),
+            ack_timeout: Duration::from_millis(500),
+            suspect_timeout: Duration::from_secs(4),
+            dissemination_multiplier: 4,
+            push_pull_interval: Duration::from_secs(30),
+            dead_member_retention: Duration::from_secs(30),
+            ..Default::default()
+        }
+    }
+
+    /// Configuration for members communicating over the Internet, similar to memberlist's `DefaultWANConfig`.
+    pub fn wan() -> Self {
+        ProtocolConfig {
+            protocol_period: Duration::from_secs(5),
+            ack_timeout: Duration::from_secs(3),
+            suspect_timeout: Duration::from_secs(30),
+            dissemination_multiplier: 4,
+            push_pull_interval: Duration::from_secs(60),
+            dead_member_retention: Duration::from_secs(60),
+            ..Default::default()
+        }
+    }
+
+    /// Configuration for members running on the same host, similar to memberlist's `DefaultLocalConfig`.
+    pub fn local() -> Self {
+        ProtocolConfig {
+            protocol_period: Duration::from_secs(1),
+            ack_timeout: Duration::from_millis(200),
+            suspect_timeout: Duration::from_secs(3),
+            dissemination_multiplier: 2,
+            push_pull_interval: Duration::from_secs(15),
+            dead_member_retention: Duration::from_secs(15),
+            ..Default::default()
+        }
+    }
+
+    /// Checks that values of the fields make sense together.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.protocol_period == Duration::from_secs(0) {
+            return Err(ConfigError::ZeroProtocolPeriod);
+        }
+        if self.ack_timeout == Duration::from_secs(0) || self.ack_timeout >= self.protocol_period {
+            return Err(ConfigError::AckTimeout {
+                ack_timeout: self.ack_timeout,
+                protocol_period: self.protocol_period,
+            });
+        }
+        if self.suspect_timeout < self.protocol_period {
+            return Err(ConfigError::SuspectTimeout {
+                suspect_timeout: self.suspect_timeout,
+                protocol_period: self.protocol_period,
+            });
+        }
+        if self.num_indirect == 0 {
+            return Err(ConfigError::NoIndirectMembers);
+        }
+        if self.suspect_max_timeout_multiplier == 0 {
+            return Err(ConfigError::Zero("suspect_max_timeout_multiplier"));
+        }
+        if self.join_max_attempts == 0 {
+            return Err(ConfigError::Zero("join_max_attempts"));
+        }
+        if self.dissemination_multiplier == 0 {
+            return Err(ConfigError::Zero("dissemination_multiplier"));
+        }
+        if self.max_local_health_multiplier == 0 {
+            return Err(ConfigError::Zero("max_local_health_multiplier"));
+        }
+        if self.secret_key.as_ref().is_some_and(|key| key.is_empty()) {
+            return Err(ConfigError::EmptySecretKey);
+        }
+        Ok(())
+    }
+
+    /// Creates configuration with timeouts given in whole seconds, as they used to be, the rest is defaulted.
+    pub fn from_secs(
+        protocol_period: u64,
+        ack_timeout: u8,
+        num_indirect: u8,
+        suspect_timeout: u64,
+        join_retry_timeout: u64,
+    ) -> Self {
+        ProtocolConfig {
+            protocol_period: Duration::from_secs(protocol_period),
+            ack_timeout: Duration::from_secs(ack_timeout as u64),
+            num_indirect,
+            suspect_timeout: Duration::from_secs(suspect_timeout),
+            join_retry_timeout: Duration::from_secs(join_retry_timeout),
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for ProtocolConfig {
     fn default() -> Self {
         Self {
-            protocol_period: 5,
-            ack_timeout: 1,
+            protocol_period: Duration::from_secs(5),
+            ack_timeout: Duration::from_secs(1),
             num_indirect: 3,
-            suspect_timeout: 15,
-            join_retry_timeout: 3,
-            notification_dissemination_times: 20,
+            suspect_timeout: Duration::from_secs(5),
+            suspect_max_timeout_multiplier: 3,
+            suspect_confirmations: 3,
+            join_retry_timeout: Duration::from_secs(3),
+            join_max_attempts: 5,
+            dissemination_multiplier: 4,
+            max_local_health_multiplier: 8,
+            push_pull_interval: Duration::from_secs(30),
+            leave_timeout: Duration::from_secs(1),
+            dead_member_retention: Duration::from_secs(60),
+            secret_key: None,
+            cluster_name: String::new(),
         }
     }
 }
+
+/// Builder of [ProtocolConfig](struct.ProtocolConfig.html) validating it before it is built.
+pub struct ProtocolConfigBuilder {
+    config: ProtocolConfig,
+}
+
+impl From<ProtocolConfig> for ProtocolConfigBuilder {
+    fn from(config: ProtocolConfig) -> Self {
+        ProtocolConfigBuilder { config }
+    }
+}
+
+impl ProtocolConfigBuilder {
+    /// Sets [ProtocolConfig::protocol_period](struct.ProtocolConfig.html#structfield.protocol_period).
+    pub fn protocol_period(mut self, protocol_period: Duration) -> Self {
+        self.config.protocol_period = protocol_period;
+        self
+    }
+
+    /// Sets [ProtocolConfig::ack_timeout](struct.ProtocolConfig.html#structfield.ack_timeout).
+    pub fn ack_timeout(mut self, ack_timeout: Duration) -> Self {
+        self.config.ack_timeout = ack_timeout;
+        self
+    }
+
+    /// Sets [ProtocolConfig::num_indirect](struct.ProtocolConfig.html#structfield.num_indirect).
+    pub fn num_indirect(mut self, num_indirect: u8) -> Self {
+        self.config.num_indirect = num_indirect;
+        self
+    }
+
+    /// Sets [ProtocolConfig::suspect_timeout](struct.ProtocolConfig.html#structfield.suspect_timeout).
+    pub fn suspect_timeout(mut self, suspect_timeout: Duration) -> Self {
+        self.config.suspect_timeout = suspect_timeout;
+        self
+    }
+
+    /// Sets [ProtocolConfig::suspect_max_timeout_multiplier](struct.ProtocolConfig.html#structfield.suspect_max_timeout_multiplier).
+    pub fn suspect_max_timeout_multiplier(mut self, suspect_max_timeout_multiplier: u32) -> Self {
+        self.config.suspect_max_timeout_multiplier = suspect_max_timeout_multiplier;
+        self
+    }
+
+    /// Sets [ProtocolConfig::suspect_confirmations](struct.ProtocolConfig.html#structfield.suspect_confirmations).
+    pub fn suspect_confirmations(mut self, suspect_confirmations: u32) -> Self {
+        self.config.suspect_confirmations = suspect_confirmations;
+        self
+    }
+
+    /// Sets [ProtocolConfig::join_retry_timeout](struct.ProtocolConfig.html#structfield.join_retry_timeout).
+    pub fn join_retry_timeout(mut self, join_retry_timeout: Duration) -> Self {
+        self.config.join_retry_timeout = join_retry_timeout;
+        self
+    }
+
+    /// Sets [ProtocolConfig::join_max_attempts](struct.ProtocolConfig.html#structfield.join_max_attempts).
+    pub fn join_max_attempts(mut self, join_max_attempts: u32) -> Self {
+        self.config.join_max_attempts = join_max_attempts;
+        self
+    }
+
+    /// Sets [ProtocolConfig::dissemination_multiplier](struct.ProtocolConfig.html#structfield.dissemination_multiplier).
+    pub fn dissemination_multiplier(mut self, dissemination_multiplier: u64) -> Self {
+        self.config.dissemination_multiplier = dissemination_multiplier;
+        self
+    }
+
+    /// Sets [ProtocolConfig::max_local_health_multiplier](struct.ProtocolConfig.html#structfield.max_local_health_multiplier).
+    pub fn max_local_health_multiplier(mut self, max_local_health_multiplier: u32) -> Self {
+        self.config.max_local_health_multiplier = max_local_health_multiplier;
+        self
+    }
+
+    /// Sets [ProtocolConfig::push_pull_interval](struct.ProtocolConfig.html#structfield.push_pull_interval).
+    pub fn push_pull_interval(mut self, push_pull_interval: Duration) -> Self {
+        self.config.push_pull_interval = push_pull_interval;
+        self
+    }
+
+    /// Sets [ProtocolConfig::leave_timeout](struct.ProtocolConfig.html#structfield.leave_timeout).
+    pub fn leave_timeout(mut self, leave_timeout: Duration) -> Self {
+        self.config.leave_timeout = leave_timeout;
+        self
+    }
+
+    /// Sets [ProtocolConfig::dead_member_retention](struct.ProtocolConfig.html#structfield.dead_member_retention).
+    pub fn dead_member_retention(mut self, dead_member_retention: Duration) -> Self {
+        self.config.dead_member_retention = dead_member_retention;
+        self
+    }
+
+    /// Sets [ProtocolConfig::secret_key](struct.ProtocolConfig.html#structfield.secret_key).
+    pub fn secret_key(mut self, secret_key: Vec<u8>) -> Self {
+        self.config.secret_key = Some(secret_key);
+        self
+    }
+
+    /// Sets [ProtocolConfig::cluster_name](struct.ProtocolConfig.html#structfield.cluster_name).
+    pub fn cluster_name(mut self, cluster_name: String) -> Self {
+        self.config.cluster_name = cluster_name;
+        self
+    }
+
+    /// Returns the configuration if it is valid.
+    pub fn build(self) -> Result<ProtocolConfig, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        ProtocolConfig::default().validate().unwrap();
+        ProtocolConfig::lan().validate().unwrap();
+        ProtocolConfig::wan().validate().unwrap();
+        ProtocolConfig::local().validate().unwrap();
+    }
+
+    #[test]
+    fn build_valid_config() {
+        let config = ProtocolConfig::builder()
+            .protocol_period(Duration::from_millis(200))
+            .ack_timeout(Duration::from_millis(50))
+            .suspect_timeout(Duration::from_secs(1))
+            .build()
+            .unwrap();
+        assert_eq!(config.protocol_period, Duration::from_millis(200));
+        assert_eq!(config.ack_timeout, Duration::from_millis(50));
+    }
+
+    #[test]
+    fn reject_ack_timeout_not_smaller_than_period() {
+        let result = ProtocolConfig::builder()
+            .protocol_period(Duration::from_secs(1))
+            .ack_timeout(Duration::from_secs(1))
+            .build();
+        match result {
+            Err(ConfigError::AckTimeout { .. }) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn reject_zero_ack_timeout() {
+        match ProtocolConfig::builder().ack_timeout(Duration::from_secs(0)).build() {
+            Err(ConfigError::AckTimeout { .. }) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn reject_suspect_timeout_shorter_than_period() {
+        let result = ProtocolConfig::builder()
+            .suspect_timeout(Duration::from_secs(1))
+            .build();
+        match result {
+            Err(e @ ConfigError::SuspectTimeout { .. }) => assert_eq!(
+                e.to_string(),
+                "suspect_timeout (1s) must not be shorter than protocol_period (5s)"
+            ),
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn reject_zero_num_indirect() {
+        match ProtocolConfig::builder().num_indirect(0).build() {
+            Err(ConfigError::NoIndirectMembers) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn reject_empty_secret_key() {
+        match ProtocolConfig::builder().secret_key(Vec::new()).build() {
+            Err(ConfigError::EmptySecretKey) => {}
+            result => panic!("Unexpected result {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn hide_secret_key() {
+        let config = ProtocolConfig::builder()
+            .secret_key(b"secret".to_vec())
+            .build()
+            .unwrap();
+        assert!(format!("{:?}", config).contains(r#"secret_key: Some("<redacted>")"#));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::member::Member;
+use sha1::{Digest, Sha1};
+
+/// First byte of every message, tells messages of this protocol apart from any other traffic.
+pub(crate) const MAGIC: u8 = 0xb5;
+
+/// Oldest version of the protocol this member understands.
+pub(crate) const MIN_VERSION: u8 = 1;
+
+/// Newest version of the protocol this member speaks.
+pub(crate) const MAX_VERSION: u8 = 2;
+
+/// First version of the protocol carrying identifier of the group in the header of every message.
+pub(crate) const CLUSTER_VERSION: u8 = 2;
+
+/// Identifier of the unnamed group, the only one members speaking older versions than `CLUSTER_VERSION` belong to.
+pub(crate) const DEFAULT_CLUSTER: u32 = 0xda39_a3ee;
+
+/// Returns identifier of the group named `cluster_name` carried in every message.
+pub(crate) fn cluster_hash(cluster_name: &str) -> u32 {
+    let digest = Sha1::digest(cluster_name.as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+}
+
+/// Returns the highest version of the protocol understood by both this member and `member`.
+pub(crate) fn negotiate(member: &Member) -> Option<u8> {
+    let version = std::cmp::min(MAX_VERSION, member.max_version);
+    if version >= std::cmp::max(MIN_VERSION, member.min_version) {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+/// Returns version of the protocol to speak with `member`, the oldest one when there is no common version.
+pub(crate) fn version_for(member: &Member) -> u8 {
+    negotiate(member).unwrap_or(MIN_VERSION)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ututils::create_member;
+
+    #[test]
+    fn negotiate_highest_common_version() {
+        let mut member = create_member(0);
+        member.min_version = MIN_VERSION;
+        member.max_version = MAX_VERSION + 1;
+        assert_eq!(negotiate(&member), Some(MAX_VERSION));
+
+        member.max_version = MIN_VERSION;
+        assert_eq!(negotiate(&member), Some(MIN_VERSION));
+    }
+
+    #[test]
+    fn hash_cluster_names() {
+        assert_eq!(cluster_hash(""), DEFAULT_CLUSTER);
+        assert_ne!(cluster_hash("staging"), cluster_hash("production"));
+    }
+
+    #[test]
+    fn no_common_version() {
+        let mut member = create_member(0);
+        member.min_version = MAX_VERSION + 1;
+        member.max_version = MAX_VERSION + 2;
+        assert_eq!(negotiate(&member), None);
+
+        member.min_version = 0;
+        member.max_version = MIN_VERSION - 1;
+        assert_eq!(negotiate(&member), None);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::authentication;
+use crate::keyring;
+use crate::member::MAX_METADATA_SIZE;
+use crate::result::Result;
+use failure::{format_err, ResultExt};
+use mio_extras::channel::Sender;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Maximal size of the state exchanged during push-pull synchronisation.
+pub(crate) const MAX_PUSH_PULL_SIZE: usize = 4 * 1024 * 1024;
+
+/// Maximal size of encoded state after it is encrypted and signed.
+const MAX_FRAME_SIZE: usize = MAX_PUSH_PULL_SIZE + keyring::OVERHEAD + authentication::MAC_SIZE;
+
+/// Upper bound of the size of the header, the sender and the number of members of encoded state.
+const MAX_STATE_HEADER_SIZE: usize = 16 + MAX_MEMBER_STATE_SIZE;
+
+/// Upper bound of the size of an encoded member state: notification header, address type, id, incarnation,
+/// IPv6 address and port, metadata with its length, protocol versions and the suspecting member.
+const MAX_MEMBER_STATE_SIZE: usize = 1 + 1 + 20 + 8 + 16 + 2 + 1 + MAX_METADATA_SIZE + 2 + 20;
+
+/// Number of members received state may always list, a joining member does not know how large the group is.
+const MIN_STATE_MEMBERS: usize = 1024;
+
+/// Maximal number of push-pull exchanges running at the same time, connections over the limit are dropped.
+const MAX_EXCHANGES: usize = 8;
+
+/// Returns maximal size of a frame with state of the group in which this member knows about `members`.
+///
+/// Others might know about more members, up to twice as many are accepted.
+pub(crate) fn max_frame_size(members: usize) -> usize {
+    let members = std::cmp::max(2 * members, MIN_STATE_MEMBERS);
+    let size = MAX_STATE_HEADER_SIZE + members * MAX_MEMBER_STATE_SIZE + keyring::OVERHEAD + authentication::MAC_SIZE;
+    std::cmp::min(size, MAX_FRAME_SIZE)
+}
+
+/// State of the other member received by a worker thread, handled on the thread of the protocol.
+///
+/// The state is only read, it is authenticated and decrypted by the protocol before it is decoded.
+#[derive(Debug)]
+pub(crate) struct Exchange {
+    pub(crate) address: SocketAddr,
+    /// Sealed state of the other member.
+    pub(crate) state: Result<Vec<u8>>,
+    /// Takes sealed state of this member when the other one has initiated the exchange.
+    pub(crate) reply: Option<SyncSender<Vec<u8>>>,
+}
+
+/// Runs push-pull exchanges on worker threads, so that the protocol is not blocked by TCP connections.
+///
+/// Received states are passed to the protocol through `exchanges`. At most `MAX_EXCHANGES` of them run at the same
+/// time, so that others can not exhaust threads and memory of this member.
+#[derive(Clone)]
+pub(crate) struct Workers {
+    exchanges: Sender<Exchange>,
+    running: Arc<AtomicUsize>,
+}
+
+impl Workers {
+    pub(crate) fn new(exchanges: Sender<Exchange>) -> Self {
+        Workers {
+            exchanges,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Sends `state` to the member at `address` and receives its state of at most `max_size` bytes.
+    pub(crate) fn initiate(
+        &self,
+        address: SocketAddr,
+        state: Vec<u8>,
+        timeout: Duration,
+        max_size: usize,
+    ) -> Result<()> {
+        self.spawn(move |exchanges| {
+            let deadline = Instant::now() + timeout;
+            let state = connect(address, timeout).and_then(|mut stream| {
+                send_state(&mut stream, &state)?;
+                recv_state(&mut stream, max_size, deadline)
+            });
+            // The protocol has stopped if nobody receives the exchange.
+            let _ = exchanges.send(Exchange {
+                address,
+                state,
+                reply: None,
+            });
+        })
+    }
+
+    /// Receives state of at most `max_size` bytes from the member which has connected and sends it back state
+    /// of this member, which the protocol provides through `Exchange::reply`.
+    pub(crate) fn respond(
+        &self,
+        mut stream: TcpStream,
+        address: SocketAddr,
+        timeout: Duration,
+        max_size: usize,
+    ) -> Result<()> {
+        self.spawn(move |exchanges| {
+            let deadline = Instant::now() + timeout;
+            let (reply, replies) = std::sync::mpsc::sync_channel(1);
+            let state = set_timeouts(&stream, timeout).and_then(|_| recv_state(&mut stream, max_size, deadline));
+            let exchange = Exchange {
+                address,
+                state,
+                reply: Some(reply),
+            };
+            if exchanges.send(exchange).is_err() {
+                return;
+            }
+            // Nothing is sent back when the state of the other member has been rejected, it notices the failure
+            // of the exchange itself.
+            if let Ok(state) = replies.recv_timeout(timeout) {
+                let _ = send_state(&mut stream, &state);
+            }
+        })
+    }
+
+    fn spawn(&self, work: impl FnOnce(&Sender<Exchange>) + Send + 'static) -> Result<()> {
+        if self.running.fetch_add(1, Ordering::SeqCst) >= MAX_EXCHANGES {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return Err(format_err!("Too many push-pull exchanges in progress"));
+        }
+        let workers = self.clone();
+        let spawned = std::thread::Builder::new()
+            .name("membership-push-pull".to_string())
+            .spawn(move || {
+                work(&workers.exchanges);
+                workers.running.fetch_sub(1, Ordering::SeqCst);
+            });
+        if let Err(e) = spawned {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+fn connect(address: SocketAddr, timeout: Duration) -> Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&address, timeout).context("Failed to connect")?;
+    set_timeouts(&stream, timeout)?;
+    Ok(stream)
+}
+
+fn set_timeouts(stream: &TcpStream, timeout: Duration) -> Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(())
+}
+
+/// Sends encoded entire state of the group, prefixed with its length.
+pub(crate) fn send_state(stream: &mut TcpStream, buffer: &[u8]) -> Result<()> {
+    stream
+        .write_all(&(buffer.len() as u32).to_be_bytes())
+        .and_then(|_| stream.write_all(buffer))
+        .and_then(|_| stream.flush())
+        .context("Failed to send state")?;
+    Ok(())
+}
+
+/// Receives encoded entire state of the group of at most `max_size` bytes sent with `send_state` until `deadline`.
+///
+/// The buffer grows only as the state arrives, so that a length prefix alone does not allocate memory.
+pub(crate) fn recv_state(stream: &mut TcpStream, max_size: usize, deadline: Instant) -> Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    stream
+        .read_exact(&mut length)
+        .context("Failed to receive state length")?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > max_size {
+        return Err(format_err!(
+            "State of {} bytes exceeds the limit of {} bytes",
+            length,
+            max_size
+        ));
+    }
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 16 * 1024];
+    while buffer.len() < length {
+        let timeout = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|timeout| *timeout > Duration::from_secs(0))
+            .ok_or_else(|| format_err!("Timed out receiving state"))?;
+        stream.set_read_timeout(Some(timeout))?;
+        let size = std::cmp::min(chunk.len(), length - buffer.len());
+        match stream.read(&mut chunk[..size]).context("Failed to receive state")? {
+            0 => {
+                return Err(format_err!(
+                    "Connection closed after {} of {} bytes of state",
+                    buffer.len(),
+                    length
+                ))
+            }
+            count => buffer.extend_from_slice(&chunk[..count]),
+        }
+    }
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::member::Member;
+    use crate::message_decoder::decode_push_pull_message;
+    use crate::message_encoder::PushPullMessageEncoder;
+    use crate::notification::Notification;
+    use mio_extras::channel::Receiver;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    #[test]
+    fn send_and_recv_state() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let sender = Member::new(SocketAddr::from_str("127.0.0.1:2345")?);
+        let members = vec![Notification::Alive {
+            member: Member::new(SocketAddr::from_str("127.0.1.1:5432")?),
+        }];
+        let message = PushPullMessageEncoder::new(MAX_PUSH_PULL_SIZE, crate::protocol_version::MAX_VERSION, 0)
+            .sender(&sender)?
+            .members(&members)?
+            .encode();
+
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        send_state(&mut client, message.buffer())?;
+        let state = decode_push_pull_message(&recv_state(&mut server, max_frame_size(1), deadline())?, 0)?;
+
+        assert_eq!(state.sender, sender);
+        assert_eq!(state.members, members);
+        Ok(())
+    }
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_secs(5)
+    }
+
+    fn recv_exchange(exchanges: &Receiver<Exchange>) -> Exchange {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Ok(exchange) = exchanges.try_recv() {
+                return exchange;
+            }
+            assert!(Instant::now() < deadline, "No exchange received");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn exchange_state_on_worker_threads() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let timeout = Duration::from_secs(5);
+        let (sender, exchanges) = mio_extras::channel::channel();
+        let workers = Workers::new(sender);
+
+        workers.initiate(address, b"initiator".to_vec(), timeout, MAX_FRAME_SIZE)?;
+        let (stream, initiator) = listener.accept()?;
+        workers.respond(stream, initiator, timeout, MAX_FRAME_SIZE)?;
+
+        let exchange = recv_exchange(&exchanges);
+        assert_eq!(exchange.address, initiator);
+        assert_eq!(exchange.state?, b"initiator".to_vec());
+        exchange.reply.unwrap().send(b"responder".to_vec())?;
+
+        let exchange = recv_exchange(&exchanges);
+        assert_eq!(exchange.address, address);
+        assert_eq!(exchange.state?, b"responder".to_vec());
+        assert!(exchange.reply.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_state_too_large_for_group() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        let max_size = max_frame_size(1);
+        client.write_all(&(max_size as u32 + 1).to_be_bytes())?;
+
+        assert!(max_size < MAX_FRAME_SIZE);
+        assert!(max_size < max_frame_size(10_000));
+        assert_eq!(max_frame_size(1_000_000), MAX_FRAME_SIZE);
+        assert!(recv_state(&mut server, max_size, deadline()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_truncated_state() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+        let (mut server, _) = listener.accept()?;
+        client.write_all(&100u32.to_be_bytes())?;
+        client.write_all(&[0u8; 10])?;
+        drop(client);
+
+        assert!(recv_state(&mut server, MAX_FRAME_SIZE, deadline()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_exchanges_over_limit() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let timeout = Duration::from_secs(5);
+        let (sender, exchanges) = mio_extras::channel::channel();
+        let workers = Workers::new(sender);
+
+        // Connections which never send their state keep the workers busy.
+        let mut clients = Vec::new();
+        for _ in 0..MAX_EXCHANGES {
+            clients.push(TcpStream::connect(address)?);
+            let (stream, client) = listener.accept()?;
+            workers.respond(stream, client, timeout, MAX_FRAME_SIZE)?;
+        }
+        let _client = TcpStream::connect(address)?;
+        let (stream, client) = listener.accept()?;
+        assert!(workers.respond(stream, client, timeout, MAX_FRAME_SIZE).is_err());
+        assert!(workers.initiate(address, Vec::new(), timeout, MAX_FRAME_SIZE).is_err());
+
+        // Worker is released after its exchange fails.
+        drop(clients.pop());
+        assert!(recv_exchange(&exchanges).state.is_err());
+        let deadline = deadline();
+        while workers.running.load(Ordering::SeqCst) == MAX_EXCHANGES {
+            assert!(Instant::now() < deadline, "Worker not released");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _client = TcpStream::connect(address)?;
+        let (stream, client) = listener.accept()?;
+        workers.respond(stream, client, timeout, MAX_FRAME_SIZE)?;
+        Ok(())
+    }
+}
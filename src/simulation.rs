@@ -0,0 +1,664 @@
+#![deny(missing_docs)]
+
+//! Members of a group running in a single process over a simulated network.
+//!
+//! Time of the simulation is virtual, it moves forward in steps only when the simulation is run, so scenarios which
+//! take minutes of protocol timeouts finish in milliseconds and do not need any sockets. The network drops, delays,
+//! duplicates and reorders datagrams with probabilities given in [NetworkConfig](struct.NetworkConfig.html), and
+//! members can be partitioned from each other. All random choices, including those of the protocol, are derived from
+//! the seed of the simulation, so a run can be repeated exactly.
+//!
+//! Push-pull synchronisation of the state of the group uses TCP and is therefore disabled in simulations.
+//!
+//! # Examples
+//! ```
+//! use membership::simulation::{NetworkConfig, Simulation};
+//! use membership::ProtocolConfig;
+//! use std::net::SocketAddr;
+//! use std::time::Duration;
+//!
+//! let addresses = (1..=3)
+//!     .map(|i| SocketAddr::from(([10, 0, 0, i], 7946)))
+//!     .collect::<Vec<_>>();
+//! let mut simulation = Simulation::new(42, NetworkConfig::default());
+//! simulation.start_node(addresses[0], ProtocolConfig::lan()).unwrap();
+//! simulation.join_node(addresses[1], ProtocolConfig::lan(), &addresses[..1]).unwrap();
+//! simulation.join_node(addresses[2], ProtocolConfig::lan(), &addresses[..1]).unwrap();
+//! simulation.run_for(Duration::from_secs(10)).unwrap();
+//! assert_eq!(simulation.members(addresses[2]), addresses);
+//! ```
+
+use crate::clock::Clock;
+use crate::error::JoinError;
+use crate::event::MembershipEvent;
+use crate::member::MemberId;
+use crate::result::Result;
+use crate::sync_node::SyncNode;
+use crate::transport::{Registry, Transport};
+use crate::ProtocolConfig;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Faults of the simulated network, applied to every datagram independently.
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    /// Probability that a datagram is lost.
+    pub loss: f64,
+    /// Probability that a datagram is delivered twice, each copy with its own delay.
+    pub duplication: f64,
+    /// Probability that a datagram is held back for an additional `max_delay` and one step of the simulation, so that
+    /// datagrams sent after it arrive first.
+    pub reordering: f64,
+    /// Shortest time it takes to deliver a datagram.
+    pub min_delay: Duration,
+    /// Longest time it takes to deliver a datagram, delays are distributed uniformly between the two.
+    pub max_delay: Duration,
+}
+
+impl Default for NetworkConfig {
+    /// Network delivering every datagram in the next step of the simulation.
+    fn default() -> Self {
+        NetworkConfig {
+            loss: 0.0,
+            duplication: 0.0,
+            reordering: 0.0,
+            min_delay: Duration::from_secs(0),
+            max_delay: Duration::from_secs(0),
+        }
+    }
+}
+
+/// Membership event observed by one of the members of a simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObservedEvent {
+    /// Time since the start of the simulation when the event happened.
+    pub time: Duration,
+    /// Address of the member which observed the event.
+    pub observer: SocketAddr,
+    /// The event itself.
+    pub event: MembershipEvent,
+}
+
+/// Group of members communicating over a simulated network on a virtual clock.
+pub struct Simulation {
+    clock: Clock,
+    started: Instant,
+    step: Duration,
+    rng: SmallRng,
+    network: Arc<Mutex<Network>>,
+    nodes: BTreeMap<SocketAddr, SyncNode>,
+    events: Arc<Mutex<Vec<ObservedEvent>>>,
+    logger: slog::Logger,
+}
+
+impl Simulation {
+    /// Creates empty simulation, all its random choices are derived from `seed`.
+    ///
+    /// Panics if probabilities in `network` are not between 0 and 1 or `min_delay` is greater than `max_delay`.
+    pub fn new(seed: u64, network: NetworkConfig) -> Self {
+        for probability in &[network.loss, network.duplication, network.reordering] {
+            assert!(
+                (0.0..=1.0).contains(probability),
+                "Probability {} is not between 0 and 1",
+                probability
+            );
+        }
+        assert!(
+            network.min_delay <= network.max_delay,
+            "Minimal delay {:?} is greater than maximal delay {:?}",
+            network.min_delay,
+            network.max_delay
+        );
+        let clock = Clock::new_virtual();
+        let step = Duration::from_millis(10);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let network = Network {
+            rng: SmallRng::seed_from_u64(rng.gen()),
+            config: network,
+            clock: clock.clone(),
+            step,
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            inboxes: HashMap::new(),
+            partitions: HashMap::new(),
+        };
+        Simulation {
+            started: clock.now(),
+            clock,
+            step,
+            rng,
+            network: Arc::new(Mutex::new(network)),
+            nodes: BTreeMap::new(),
+            events: Arc::new(Mutex::new(Vec::new())),
+            logger: slog::Logger::root(slog::Discard, slog::o!()),
+        }
+    }
+
+    /// Sets how far the virtual clock moves in every step of the simulation, 10 ms by default.
+    ///
+    /// Members handle received datagrams and their timeouts once per step, so the step should be well below
+    /// the shortest timeout of the protocol.
+    pub fn set_step(&mut self, step: Duration) {
+        assert!(step > Duration::from_secs(0), "Step has to be longer than zero");
+        self.step = step;
+        self.network.lock().unwrap().step = step;
+    }
+
+    /// Sets logger of members added afterwards, each of them logs with its address.
+    pub fn set_logger(&mut self, logger: slog::Logger) {
+        self.logger = logger;
+    }
+
+    /// Adds member at `address` starting new group.
+    ///
+    /// Fails with [ConfigError](../enum.ConfigError.html) if the configuration is not valid.
+    pub fn start_node(&mut self, address: SocketAddr, config: ProtocolConfig) -> Result<()> {
+        let node = self.create_node(address, config)?;
+        self.nodes.insert(address, node);
+        Ok(())
+    }
+
+    /// Adds member at `address` joining the group through any of the `seeds`.
+    ///
+    /// Fails with [ConfigError](../enum.ConfigError.html) if the configuration is not valid and with
+    /// `JoinError::NoSeeds` if `seeds` is empty. If none of the seeds answers, [run_for](#method.run_for) fails with
+    /// [JoinError](../enum.JoinError.html) and the member is removed.
+    pub fn join_node(&mut self, address: SocketAddr, config: ProtocolConfig, seeds: &[SocketAddr]) -> Result<()> {
+        if seeds.is_empty() {
+            return Err(JoinError::NoSeeds.into());
+        }
+        let mut node = self.create_node(address, config)?;
+        node.begin_join(seeds.to_vec(), None)?;
+        self.nodes.insert(address, node);
+        Ok(())
+    }
+
+    fn create_node(&mut self, address: SocketAddr, config: ProtocolConfig) -> Result<SyncNode> {
+        assert!(!self.nodes.contains_key(&address), "Member {} already exists", address);
+        config.validate()?;
+        let (mut node, _) = SyncNode::new(address, config);
+        let mut id = [0u8; 20];
+        self.rng.fill(&mut id);
+        node.set_id(MemberId::try_from(id.as_ref())?);
+        node.set_logger(self.logger.new(slog::o!("address" => address.to_string())));
+        node.set_rng(SmallRng::seed_from_u64(self.rng.gen()));
+        node.set_clock(self.clock.clone());
+        node.set_transport(Box::new(SimulatedTransport {
+            address,
+            network: self.network.clone(),
+        }));
+        let events = self.events.clone();
+        let clock = self.clock.clone();
+        let started = self.started;
+        node.subscribe(Box::new(move |event| {
+            events.lock().unwrap().push(ObservedEvent {
+                time: clock.now() - started,
+                observer: address,
+                event,
+            });
+            true
+        }));
+        self.network.lock().unwrap().inboxes.insert(address, VecDeque::new());
+        Ok(node)
+    }
+
+    /// Stops member at `address` gracefully, it leaves the group while the simulation runs.
+    pub fn stop_node(&mut self, address: SocketAddr) {
+        let node = self.nodes.get_mut(&address).expect("Unknown member");
+        if !node.leave() {
+            self.remove_node(address);
+        }
+    }
+
+    /// Stops member at `address` abruptly, as if its process crashed, datagrams sent to it are lost.
+    pub fn crash_node(&mut self, address: SocketAddr) {
+        assert!(self.nodes.contains_key(&address), "Unknown member");
+        self.remove_node(address);
+    }
+
+    fn remove_node(&mut self, address: SocketAddr) {
+        self.nodes.remove(&address);
+        self.network.lock().unwrap().inboxes.remove(&address);
+    }
+
+    /// Separates members at addresses in `group` from all the other members, including other partitions.
+    ///
+    /// Datagrams already in flight between them are dropped as well.
+    pub fn partition(&mut self, group: &[SocketAddr]) {
+        let mut network = self.network.lock().unwrap();
+        let partition = network.partitions.values().max().map_or(1, |p| p + 1);
+        for address in group {
+            network.partitions.insert(*address, partition);
+        }
+    }
+
+    /// Removes all partitions, datagrams sent before still do not cross them.
+    pub fn heal(&mut self) {
+        self.network.lock().unwrap().partitions.clear();
+    }
+
+    /// Runs the simulation until the virtual clock moves by `duration`.
+    ///
+    /// Fails with the first error of a member, e.g. [JoinError](../enum.JoinError.html), removing the member.
+    /// The step in which it happened is finished by all the other members first.
+    pub fn run_for(&mut self, duration: Duration) -> Result<()> {
+        let end = self.clock.now() + duration;
+        while self.clock.now() < end {
+            let mut error = None;
+            self.clock.advance(self.step);
+            self.network.lock().unwrap().deliver();
+            let addresses = self.nodes.keys().cloned().collect::<Vec<_>>();
+            for address in addresses {
+                let received = self.network.lock().unwrap().inboxes[&address].len();
+                let node = self.nodes.get_mut(&address).unwrap();
+                (0..received).for_each(|_| node.handle_protocol_event());
+                let result = node.tick();
+                if result.is_err() || node.has_left() {
+                    self.remove_node(address);
+                }
+                if let Err(e) = result {
+                    error.get_or_insert(e);
+                }
+            }
+            if let Some(e) = error {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns time since the start of the simulation.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now() - self.started
+    }
+
+    /// Returns whether member at `address` is running, i.e. it has neither stopped nor crashed.
+    pub fn is_running(&self, address: SocketAddr) -> bool {
+        self.nodes.contains_key(&address)
+    }
+
+    /// Returns sorted addresses of all alive members of the group the member at `address` knows about, including
+    /// itself.
+    pub fn members(&self, address: SocketAddr) -> Vec<SocketAddr> {
+        let mut members = self
+            .nodes
+            .get(&address)
+            .expect("Unknown member")
+            .get_members_addresses();
+        members.sort();
+        members
+    }
+
+    /// Returns all membership events observed by the members so far, in the order they happened.
+    pub fn events(&self) -> Vec<ObservedEvent> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+struct Datagram {
+    sender: SocketAddr,
+    target: SocketAddr,
+    payload: Vec<u8>,
+}
+
+/// Datagrams in flight and received by members, shared by transports of all members of a simulation.
+struct Network {
+    rng: SmallRng,
+    config: NetworkConfig,
+    clock: Clock,
+    step: Duration,
+    // Ordered by the time of delivery, then by the order of sending, with whether a partition separated
+    // the sender from the target when it was sent.
+    in_flight: BTreeMap<(Instant, u64), (Datagram, bool)>,
+    sent: u64,
+    inboxes: HashMap<SocketAddr, VecDeque<Datagram>>,
+    // Members which are not in any partition are in partition 0.
+    partitions: HashMap<SocketAddr, usize>,
+}
+
+impl Network {
+    fn send(&mut self, datagram: Datagram) {
+        if self.rng.gen_bool(self.config.loss) {
+            return;
+        }
+        if self.rng.gen_bool(self.config.duplication) {
+            let duplicate = Datagram {
+                payload: datagram.payload.clone(),
+                ..datagram
+            };
+            self.schedule(duplicate);
+        }
+        self.schedule(datagram);
+    }
+
+    fn schedule(&mut self, datagram: Datagram) {
+        let jitter = (self.config.max_delay - self.config.min_delay).as_nanos() as u64;
+        let mut delay = self.config.min_delay + Duration::from_nanos(self.rng.gen_range(0, jitter + 1));
+        if self.rng.gen_bool(self.config.reordering) {
+            delay += self.config.max_delay + self.step;
+        }
+        self.sent += 1;
+        let separated = self.separated(&datagram);
+        self.in_flight
+            .insert((self.clock.now() + delay, self.sent), (datagram, separated));
+    }
+
+    fn separated(&self, datagram: &Datagram) -> bool {
+        let partition = |address| self.partitions.get(address).cloned().unwrap_or(0);
+        partition(&datagram.sender) != partition(&datagram.target)
+    }
+
+    /// Moves datagrams due by now to inboxes of their targets, unless a partition separated them from the sender
+    /// when they were sent or separates them now.
+    fn deliver(&mut self) {
+        let now = self.clock.now();
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            let (datagram, separated) = entry.remove();
+            if separated || self.separated(&datagram) {
+                continue;
+            }
+            if let Some(inbox) = self.inboxes.get_mut(&datagram.target) {
+                inbox.push_back(datagram);
+            }
+        }
+    }
+}
+
+/// Transport of a member of a simulation, it is never polled.
+struct SimulatedTransport {
+    address: SocketAddr,
+    network: Arc<Mutex<Network>>,
+}
+
+impl Transport for SimulatedTransport {
+    fn register(&mut self, _registry: &Registry) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn send_to(&self, buffer: &[u8], target: SocketAddr) -> io::Result<usize> {
+        self.network.lock().unwrap().send(Datagram {
+            sender: self.address,
+            target,
+            payload: buffer.to_vec(),
+        });
+        Ok(buffer.len())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut network = self.network.lock().unwrap();
+        match network
+            .inboxes
+            .get_mut(&self.address)
+            .and_then(|inbox| inbox.pop_front())
+        {
+            Some(datagram) => {
+                let count = std::cmp::min(buffer.len(), datagram.payload.len());
+                buffer[..count].copy_from_slice(&datagram.payload[..count]);
+                Ok((count, datagram.sender))
+            }
+            None => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addresses(count: u8) -> Vec<SocketAddr> {
+        (1..=count).map(|i| SocketAddr::from(([10, 0, 0, i], 7946))).collect()
+    }
+
+    fn config() -> ProtocolConfig {
+        ProtocolConfig {
+            suspect_timeout: Duration::from_secs(5),
+            ..ProtocolConfig::lan()
+        }
+    }
+
+    fn create_group(seed: u64, network: NetworkConfig, addresses: &[SocketAddr]) -> Simulation {
+        let mut simulation = Simulation::new(seed, network);
+        simulation.start_node(addresses[0], config()).unwrap();
+        for address in &addresses[1..] {
+            simulation.join_node(*address, config(), &addresses[..1]).unwrap();
+        }
+        simulation.run_for(Duration::from_secs(10)).unwrap();
+        simulation
+    }
+
+    fn events_about(simulation: &Simulation, member: SocketAddr) -> Vec<ObservedEvent> {
+        simulation
+            .events()
+            .into_iter()
+            .filter(|e| e.event.member().address == member)
+            .collect()
+    }
+
+    #[test]
+    fn join_group() {
+        let addresses = addresses(5);
+        let simulation = create_group(1, NetworkConfig::default(), &addresses);
+
+        for address in &addresses {
+            assert_eq!(simulation.members(*address), addresses);
+        }
+        let joined = simulation
+            .events()
+            .iter()
+            .filter(|e| matches!(e.event, MembershipEvent::Joined(_)))
+            .count();
+        assert_eq!(joined, 5 * 4);
+    }
+
+    #[test]
+    fn confirm_crashed_member_dead() {
+        let addresses = addresses(5);
+        let mut simulation = create_group(2, NetworkConfig::default(), &addresses);
+
+        simulation.crash_node(addresses[4]);
+        simulation.run_for(Duration::from_secs(60)).unwrap();
+
+        for address in &addresses[..4] {
+            assert_eq!(simulation.members(*address), &addresses[..4]);
+        }
+        let events = events_about(&simulation, addresses[4]);
+        assert!(events.iter().any(|e| matches!(e.event, MembershipEvent::Suspected(_))));
+        for observer in &addresses[..4] {
+            assert!(events
+                .iter()
+                .any(|e| e.observer == *observer && matches!(e.event, MembershipEvent::Dead(_))));
+        }
+    }
+
+    #[test]
+    fn refute_suspicion_after_partition() {
+        let addresses = addresses(5);
+        let mut simulation = create_group(3, NetworkConfig::default(), &addresses);
+        simulation.partition(&addresses[4..]);
+        simulation.run_for(config().protocol_period * 2).unwrap();
+        simulation.heal();
+        simulation.run_for(Duration::from_secs(30)).unwrap();
+
+        for address in &addresses {
+            assert_eq!(simulation.members(*address), addresses);
+        }
+        let events = events_about(&simulation, addresses[4]);
+        assert!(events.iter().any(|e| matches!(e.event, MembershipEvent::Suspected(_))));
+        assert!(events.iter().any(|e| matches!(e.event, MembershipEvent::Alive(_))));
+        assert!(!events.iter().any(|e| matches!(e.event, MembershipEvent::Dead(_))));
+    }
+
+    #[test]
+    fn shorten_suspicion_with_confirmations() {
+        let addresses = addresses(6);
+        let config = config();
+        let max_timeout = config.suspect_timeout * config.suspect_max_timeout_multiplier;
+        let mut timeouts = (0..20)
+            .map(|seed| {
+                let mut simulation = create_group(seed, NetworkConfig::default(), &addresses);
+                simulation.crash_node(addresses[5]);
+                simulation.run_for(max_timeout * 2).unwrap();
+
+                let events = events_about(&simulation, addresses[5]);
+                let first = |f: fn(&MembershipEvent) -> bool| events.iter().find(|e| f(&e.event)).unwrap().time;
+                first(|e| matches!(e, MembershipEvent::Dead(_))) - first(|e| matches!(e, MembershipEvent::Suspected(_)))
+            })
+            .collect::<Vec<_>>();
+        timeouts.sort();
+
+        // All the other members confirm the suspicion, so it usually times out close to the minimal timeout.
+        assert!(
+            timeouts[timeouts.len() / 2] < config.suspect_timeout * 2,
+            "{:?}",
+            timeouts
+        );
+        assert!(timeouts[timeouts.len() - 1] < max_timeout, "{:?}", timeouts);
+    }
+
+    #[test]
+    fn remove_member_which_left() {
+        let addresses = addresses(3);
+        let mut simulation = create_group(4, NetworkConfig::default(), &addresses);
+
+        simulation.stop_node(addresses[2]);
+        simulation.run_for(Duration::from_secs(5)).unwrap();
+
+        assert!(!simulation.is_running(addresses[2]));
+        for address in &addresses[..2] {
+            assert_eq!(simulation.members(*address), &addresses[..2]);
+        }
+        let events = events_about(&simulation, addresses[2]);
+        assert!(events.iter().any(|e| matches!(e.event, MembershipEvent::Left(_))));
+    }
+
+    #[test]
+    fn fail_to_join_without_seed() {
+        let addresses = addresses(2);
+        let mut simulation = Simulation::new(5, NetworkConfig::default());
+        simulation.join_node(addresses[0], config(), &addresses[1..]).unwrap();
+
+        let error = simulation.run_for(Duration::from_secs(60)).unwrap_err();
+
+        assert!(error.downcast_ref::<JoinError>().is_some());
+        assert!(!simulation.is_running(addresses[0]));
+    }
+
+    #[test]
+    fn finish_step_before_failing() {
+        let addresses = addresses(3);
+        let mut simulation = Simulation::new(5, NetworkConfig::default());
+        simulation.join_node(addresses[0], config(), &addresses[2..]).unwrap();
+        simulation.join_node(addresses[1], config(), &addresses[2..]).unwrap();
+
+        assert!(simulation.run_for(Duration::from_secs(60)).is_err());
+
+        // Both members give up in the same step.
+        assert!(!simulation.is_running(addresses[0]));
+        assert!(!simulation.is_running(addresses[1]));
+    }
+
+    #[test]
+    fn reject_join_without_seeds() {
+        let addresses = addresses(1);
+        let mut simulation = Simulation::new(5, NetworkConfig::default());
+
+        let error = simulation.join_node(addresses[0], config(), &[]).unwrap_err();
+
+        assert!(matches!(error.downcast_ref::<JoinError>(), Some(JoinError::NoSeeds)));
+        assert!(!simulation.is_running(addresses[0]));
+    }
+
+    #[test]
+    fn converge_over_faulty_network() {
+        let network = NetworkConfig {
+            loss: 0.1,
+            duplication: 0.1,
+            reordering: 0.1,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+        };
+        let addresses = addresses(8);
+        let mut simulation = create_group(6, network, &addresses);
+        simulation.run_for(Duration::from_secs(20)).unwrap();
+
+        for address in &addresses {
+            assert_eq!(simulation.members(*address), addresses);
+        }
+    }
+
+    #[test]
+    fn repeat_run_with_same_seed() {
+        let network = NetworkConfig {
+            loss: 0.2,
+            duplication: 0.1,
+            reordering: 0.1,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(100),
+        };
+        let run = |seed| {
+            let addresses = addresses(6);
+            let mut simulation = create_group(seed, network.clone(), &addresses);
+            simulation.crash_node(addresses[5]);
+            simulation.run_for(Duration::from_secs(30)).unwrap();
+            simulation.events()
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+    }
+
+    #[test]
+    fn drop_datagrams_crossing_partition() {
+        let addresses = addresses(3);
+        let mut simulation = Simulation::new(9, NetworkConfig::default());
+        simulation.partition(&addresses[..1]);
+        simulation.partition(&addresses[1..2]);
+        let mut network = simulation.network.lock().unwrap();
+        for address in &addresses {
+            network.inboxes.insert(*address, VecDeque::new());
+        }
+        for (sender, target) in &[(0, 1), (1, 2), (2, 0), (1, 1)] {
+            network.send(Datagram {
+                sender: addresses[*sender],
+                target: addresses[*target],
+                payload: vec![],
+            });
+        }
+        network.deliver();
+
+        assert!(network.inboxes[&addresses[0]].is_empty());
+        assert_eq!(network.inboxes[&addresses[1]].len(), 1);
+        assert!(network.inboxes[&addresses[2]].is_empty());
+
+        // Datagrams in flight do not cross partitions healed or created in the meantime.
+        network.send(Datagram {
+            sender: addresses[0],
+            target: addresses[1],
+            payload: vec![],
+        });
+        drop(network);
+        simulation.heal();
+        let mut network = simulation.network.lock().unwrap();
+        network.send(Datagram {
+            sender: addresses[0],
+            target: addresses[1],
+            payload: vec![],
+        });
+        drop(network);
+        simulation.partition(&addresses[..1]);
+        let mut network = simulation.network.lock().unwrap();
+        network.deliver();
+
+        assert!(network.inboxes[&addresses[0]].is_empty());
+        assert_eq!(network.inboxes[&addresses[1]].len(), 1);
+    }
+}
@@ -1,16 +1,119 @@
-use crate::member::Member;
-use std::time::Instant;
+use crate::member::{Member, MemberId};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 
+/// Suspicion with a timeout shrinking as independent members confirm it (Lifeguard).
+///
+/// The timeout starts at `max_timeout` and approaches `min_timeout` logarithmically, reaching it when
+/// `expected_confirmations` members other than the one that raised the suspicion have suspected the member too.
 pub(crate) struct Suspicion {
     pub(crate) created: Instant,
     pub(crate) member: Member,
+    suspecting: HashSet<MemberId>,
+    min_timeout: Duration,
+    max_timeout: Duration,
+    expected_confirmations: u32,
 }
 
 impl Suspicion {
-    pub(crate) fn new(member: Member) -> Self {
+    pub(crate) fn new(
+        member: Member,
+        from: MemberId,
+        created: Instant,
+        min_timeout: Duration,
+        max_timeout: Duration,
+        expected_confirmations: u32,
+    ) -> Self {
+        let mut suspecting = HashSet::new();
+        suspecting.insert(from);
         Suspicion {
-            created: Instant::now(),
+            created,
             member,
+            suspecting,
+            min_timeout,
+            max_timeout,
+            expected_confirmations,
         }
     }
+
+    /// Records that `from` suspects the member as well, returns `false` if it has already been recorded.
+    pub(crate) fn confirm(&mut self, from: MemberId) -> bool {
+        self.suspecting.insert(from)
+    }
+
+    pub(crate) fn confirmations(&self) -> u32 {
+        (self.suspecting.len() - 1) as u32
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        if self.expected_confirmations == 0 || self.max_timeout <= self.min_timeout {
+            return self.min_timeout;
+        }
+        let fraction = (self.confirmations() as f64 + 1.0).ln() / (self.expected_confirmations as f64 + 1.0).ln();
+        let range = (self.max_timeout - self.min_timeout).as_secs_f64();
+        let timeout = self.max_timeout.as_secs_f64() - fraction * range;
+        std::cmp::max(Duration::from_secs_f64(timeout.max(0.0)), self.min_timeout)
+    }
+
+    pub(crate) fn deadline(&self) -> Instant {
+        self.created + self.timeout()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ututils::create_members;
+    use std::convert::TryFrom;
+
+    fn create_suspicion(expected_confirmations: u32) -> Suspicion {
+        let members = create_members(1);
+        Suspicion::new(
+            members[0].clone(),
+            MemberId::try_from([1u8; 20].as_ref()).unwrap(),
+            Instant::now(),
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+            expected_confirmations,
+        )
+    }
+
+    #[test]
+    fn start_with_max_timeout() {
+        assert_eq!(create_suspicion(3).timeout(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn min_timeout_when_no_confirmations_expected() {
+        assert_eq!(create_suspicion(0).timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn shrink_with_confirmations() {
+        let mut suspicion = create_suspicion(3);
+
+        assert!(suspicion.confirm(MemberId::try_from([2u8; 20].as_ref()).unwrap()));
+        let after_one = suspicion.timeout();
+        assert!(after_one < Duration::from_secs(60));
+        assert!(after_one > Duration::from_secs(10));
+
+        assert!(suspicion.confirm(MemberId::try_from([3u8; 20].as_ref()).unwrap()));
+        assert!(suspicion.timeout() < after_one);
+
+        assert!(suspicion.confirm(MemberId::try_from([4u8; 20].as_ref()).unwrap()));
+        assert_eq!(suspicion.timeout(), Duration::from_secs(10));
+
+        assert!(suspicion.confirm(MemberId::try_from([5u8; 20].as_ref()).unwrap()));
+        assert_eq!(suspicion.timeout(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn ignore_repeated_confirmations() {
+        let mut suspicion = create_suspicion(3);
+
+        assert!(!suspicion.confirm(MemberId::try_from([1u8; 20].as_ref()).unwrap()));
+        assert!(suspicion.confirm(MemberId::try_from([2u8; 20].as_ref()).unwrap()));
+        assert!(!suspicion.confirm(MemberId::try_from([2u8; 20].as_ref()).unwrap()));
+        assert_eq!(suspicion.confirmations(), 1);
+    }
 }
@@ -1,27 +1,48 @@
 #![deny(missing_docs)]
 
+use crate::authentication::{self, AuthenticationError};
+use crate::clock::Clock;
+use crate::delegate::Delegate;
 use crate::disseminated::Disseminated;
-use crate::incoming_message::{DisseminationMessageIn, IncomingMessage, PingRequestMessageIn};
-use crate::member::{Member, MemberId};
+use crate::error::{JoinError, KeyringError};
+use crate::event::{MemberInfo, MembershipEvent};
+use crate::incoming_message::{DisseminationMessageIn, IncomingMessage, PingRequestMessageIn, PushPullMessageIn};
+use crate::key_management::{KeyMessage, KeyOperation, KeyReport};
+use crate::keyring::{DecryptionError, Keyring};
+use crate::local_health::LocalHealth;
+use crate::member::{Member, MemberId, MAX_METADATA_SIZE};
 use crate::message::MessageType;
-use crate::message_decoder::decode_message;
-use crate::message_encoder::{DisseminationMessageEncoder, OutgoingMessage, PingRequestMessageEncoder};
+use crate::message_decoder::{decode_message, decode_push_pull_message, ForeignClusterError};
+use crate::message_encoder::{
+    DisseminationMessageEncoder, OutgoingMessage, PingRequestMessageEncoder, PushPullMessageEncoder,
+};
+use crate::metrics::Metrics;
 use crate::notification::Notification;
+use crate::protocol_version::{self, version_for};
+use crate::push_pull::{self, MAX_PUSH_PULL_SIZE};
 use crate::result::Result;
 use crate::suspicion::Suspicion;
+use crate::transport::{Readiness, Registry, Transport};
+use crate::unique_circular_buffer::UniqueCircularBuffer;
+use crate::user_message::UserMessage;
 use crate::ProtocolConfig;
 use failure::{format_err, ResultExt};
-use mio::net::UdpSocket;
-use mio::{Event, Events, Poll, PollOpt, Ready, Token};
+use mio::net::{TcpListener, UdpSocket};
+use mio::{Events, Poll, PollOpt, Ready, Registration, Token};
 use mio_extras::channel::{Receiver, Sender};
 use rand::rngs::SmallRng;
-use rand::seq::SliceRandom;
+use rand::seq::{IteratorRandom, SliceRandom};
 use rand::SeedableRng;
 use slog::{debug, info, warn};
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Fraction of `ack_timeout` after which a proxy sends nack for the indirect probe (as suggested by Lifeguard), so
+/// that the nack reaches the requester before it gives up on the probe.
+const NACK_TIMEOUT_FRACTION: f64 = 0.8;
 
 struct IncomingLetter {
     sender: SocketAddr,
@@ -47,14 +68,25 @@ struct Header {
 #[derive(Debug)]
 struct Ack {
     request: Request,
-    request_time: std::time::Instant,
+    request_time: Instant,
+    expected_nacks: usize,
+    nacks: usize,
 }
 
 impl Ack {
-    fn new(request: Request) -> Self {
+    fn new(request: Request, request_time: Instant) -> Self {
         Ack {
             request,
-            request_time: std::time::Instant::now(),
+            request_time,
+            expected_nacks: 0,
+            nacks: 0,
+        }
+    }
+
+    fn with_expected_nacks(request: Request, request_time: Instant, expected_nacks: usize) -> Self {
+        Ack {
+            expected_nacks,
+            ..Ack::new(request, request_time)
         }
     }
 }
@@ -80,29 +112,105 @@ enum Request {
     PingProxy(PingProxyRequest),
     Ack(Header),
     AckIndirect(AckIndirectRequest),
+    NackIndirect(AckIndirectRequest),
+}
+
+/// Change of keys requested from all members of the group, waiting for their responses.
+#[derive(Debug)]
+struct KeyQuery {
+    key_size: usize,
+    pending: HashMap<MemberId, SocketAddr>,
+    report: KeyReport,
+    reply: std::sync::mpsc::SyncSender<std::result::Result<KeyReport, KeyringError>>,
+}
+
+/// Notified about members of the group once the member joins it.
+pub(crate) type Joined = Box<dyn FnOnce(Vec<SocketAddr>) + Send>;
+
+/// Receives membership events, returns whether it wants more of them.
+pub(crate) type EventSubscriber = Box<dyn Fn(MembershipEvent) -> bool + Send>;
+
+/// Progress of joining the group through one of the seed members.
+struct Join {
+    seeds: Vec<SocketAddr>,
+    remaining: Vec<SocketAddr>,
+    attempt: u32,
+    joined: Option<Joined>,
+    /// Whether the member has joined and waits for push-pull synchronisation with the seed to notify `joined`.
+    synchronising: bool,
+}
+
+/// Function run on the thread of the protocol, giving front-ends access to its state.
+#[cfg(feature = "tokio")]
+pub(crate) struct Call(pub(crate) Box<dyn FnOnce(&mut SyncNode) + Send>);
+
+#[cfg(feature = "tokio")]
+impl std::fmt::Debug for Call {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Call")
+    }
 }
 
 #[derive(Debug)]
 pub(crate) enum ChannelMessage {
     Stop,
     GetMembers(std::sync::mpsc::SyncSender<Vec<SocketAddr>>),
+    GetMembersWithMetadata(std::sync::mpsc::SyncSender<Vec<(SocketAddr, Vec<u8>)>>),
+    SetMetadata(Vec<u8>),
+    GetDeadMembersCount(std::sync::mpsc::SyncSender<usize>),
+    Broadcast(Vec<u8>),
+    SubscribeBroadcasts(std::sync::mpsc::Sender<Vec<u8>>),
+    Subscribe(std::sync::mpsc::Sender<MembershipEvent>),
+    #[cfg(feature = "tokio")]
+    Call(Call),
+    GetMetrics(std::sync::mpsc::SyncSender<Metrics>),
+    InstallKey(
+        Vec<u8>,
+        std::sync::mpsc::SyncSender<std::result::Result<(), KeyringError>>,
+    ),
+    UseKey(
+        Vec<u8>,
+        std::sync::mpsc::SyncSender<std::result::Result<(), KeyringError>>,
+    ),
+    RemoveKey(
+        Vec<u8>,
+        std::sync::mpsc::SyncSender<std::result::Result<(), KeyringError>>,
+    ),
+    ChangeGroupKeys(
+        KeyOperation,
+        Vec<u8>,
+        Duration,
+        std::sync::mpsc::SyncSender<std::result::Result<KeyReport, KeyringError>>,
+    ),
 }
 
 // Unfortunately SyncNode needs to be passed explicitly, it cannot be captured by closure.
 struct Timeout<F: FnOnce(&mut SyncNode)> {
-    when: std::time::Instant,
+    when: Instant,
     what: F,
 }
 
+type BoxedTimeout = Timeout<Box<dyn FnOnce(&mut SyncNode) + Send>>;
+
 /// Runs the protocol on current thread, blocking it.
 pub(crate) struct SyncNode {
     config: ProtocolConfig,
-    udp: Option<UdpSocket>,
+    cluster: u32,
+    transport: Option<Box<dyn Transport>>,
+    transport_readiness: Option<(Registration, Readiness)>,
+    tcp: Option<TcpListener>,
+    push_pull_workers: push_pull::Workers,
+    exchange_receiver: Receiver<push_pull::Exchange>,
     ping_order: Vec<MemberId>,
     broadcast: Disseminated<MemberId>,
     notifications: Disseminated<Notification>,
+    user_messages: Disseminated<UserMessage>,
+    delivered_user_messages: HashMap<(MemberId, u64), Instant>,
+    user_message_sequence_number: u64,
+    broadcast_subscribers: Vec<std::sync::mpsc::Sender<Vec<u8>>>,
+    event_subscribers: Vec<EventSubscriber>,
     members: HashMap<MemberId, Member>,
-    dead_members: HashSet<MemberId>,
+    dead_members: HashMap<MemberId, Instant>,
     next_member_index: usize,
     epoch: u64,
     sequence_number: u64,
@@ -112,22 +220,47 @@ pub(crate) struct SyncNode {
     receiver: Receiver<ChannelMessage>,
     acks: Vec<Ack>,
     rng: SmallRng,
+    clock: Clock,
+    last_epoch_time: Instant,
+    last_push_pull_time: Instant,
     suspicions: VecDeque<Suspicion>,
-    timeouts: Vec<Timeout<Box<dyn FnOnce(&mut SyncNode) + Send>>>,
+    local_health: LocalHealth,
+    timeouts: Vec<BoxedTimeout>,
+    leave_deadline: Option<Instant>,
+    join: Option<Join>,
+    metrics: Metrics,
+    keyring: Keyring,
+    key_messages: Disseminated<KeyMessage>,
+    handled_key_messages: UniqueCircularBuffer<(MemberId, u64, Option<MemberId>)>,
+    key_sequence_number: u64,
+    key_queries: HashMap<u64, KeyQuery>,
+    delegate: Option<Box<dyn Delegate>>,
     logger: slog::Logger,
 }
 
 impl SyncNode {
     pub(crate) fn new(bind_address: SocketAddr, config: ProtocolConfig) -> (SyncNode, Sender<ChannelMessage>) {
         let (sender, receiver) = mio_extras::channel::channel();
+        let (exchange_sender, exchange_receiver) = mio_extras::channel::channel();
+        let local_health = LocalHealth::new(config.max_local_health_multiplier);
         let gossip = SyncNode {
+            cluster: protocol_version::cluster_hash(&config.cluster_name),
             config,
-            udp: None,
+            transport: None,
+            transport_readiness: None,
+            tcp: None,
+            push_pull_workers: push_pull::Workers::new(exchange_sender),
+            exchange_receiver,
             ping_order: vec![],
             broadcast: Disseminated::new(),
             notifications: Disseminated::new(),
+            user_messages: Disseminated::new(),
+            delivered_user_messages: HashMap::new(),
+            user_message_sequence_number: 0,
+            broadcast_subscribers: Vec::new(),
+            event_subscribers: Vec::new(),
             members: HashMap::new(),
-            dead_members: HashSet::new(),
+            dead_members: HashMap::new(),
             next_member_index: 0,
             epoch: 0,
             sequence_number: 0,
@@ -137,8 +270,21 @@ impl SyncNode {
             receiver,
             acks: Vec::<Ack>::with_capacity(32),
             rng: SmallRng::from_entropy(),
+            clock: Clock::System,
+            last_epoch_time: Instant::now(),
+            last_push_pull_time: Instant::now(),
             suspicions: VecDeque::new(),
+            local_health,
             timeouts: Vec::new(),
+            leave_deadline: None,
+            join: None,
+            metrics: Metrics::default(),
+            keyring: Keyring::default(),
+            key_messages: Disseminated::new(),
+            handled_key_messages: UniqueCircularBuffer::new(1024),
+            key_sequence_number: 0,
+            key_queries: HashMap::new(),
+            delegate: None,
             logger: slog::Logger::root(slog::Discard, slog::o!()),
         };
         (gossip, sender)
@@ -148,39 +294,139 @@ impl SyncNode {
         self.logger = logger.new(slog::o!("id" => self.myself.id.to_string()));
     }
 
+    /// Sets metadata of this member, before it starts communicating with others.
+    pub(crate) fn set_metadata(&mut self, metadata: Vec<u8>) {
+        self.myself.metadata = metadata;
+    }
+
+    /// Sets keys encrypting messages, before the member starts communicating with others.
+    pub(crate) fn set_keyring(&mut self, keyring: Keyring) {
+        self.keyring = keyring;
+    }
+
+    /// Sets transport of the protocol messages used instead of UDP socket bound to the address of this member.
+    pub(crate) fn set_transport(&mut self, transport: Box<dyn Transport>) {
+        self.transport = Some(transport);
+    }
+
+    /// Sets hooks of the application into the protocol, before the member starts communicating with others.
+    pub(crate) fn set_delegate(&mut self, delegate: Box<dyn Delegate>) {
+        self.delegate = Some(delegate);
+    }
+
+    /// Sets source of the current time, before the member starts communicating with others.
+    pub(crate) fn set_clock(&mut self, clock: Clock) {
+        self.last_epoch_time = clock.now();
+        self.last_push_pull_time = clock.now();
+        self.clock = clock;
+    }
+
+    /// Sets generator of random choices of the protocol, making them repeatable when it is seeded.
+    pub(crate) fn set_rng(&mut self, rng: SmallRng) {
+        self.rng = rng;
+    }
+
+    /// Sets identifier of this member, before it starts communicating with others.
+    pub(crate) fn set_id(&mut self, id: MemberId) {
+        self.myself.id = id;
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
     pub(crate) fn start(&mut self) -> Result<()> {
+        if let Some(metadata) = self.delegate.as_mut().and_then(|d| d.node_metadata(MAX_METADATA_SIZE)) {
+            if metadata.len() <= MAX_METADATA_SIZE {
+                self.myself.metadata = metadata;
+            } else {
+                warn!(
+                    self.logger,
+                    "Ignoring metadata of {} bytes provided by delegate",
+                    metadata.len()
+                );
+            }
+        }
         let poll = Poll::new().unwrap();
         poll.register(&self.receiver, Token(1), Ready::readable(), PollOpt::empty())?;
         self.bind(&poll)?;
 
         let mut events = Events::with_capacity(1024);
-        let mut last_epoch_time = std::time::Instant::now();
+        self.last_epoch_time = self.now();
+        self.last_push_pull_time = self.now();
+        // Wake up often enough not to delay handling of acks with sub-second timeouts.
+        let poll_timeout = std::cmp::min(Duration::from_millis(100), self.config.ack_timeout / 2);
 
         'mainloop: loop {
-            poll.poll(&mut events, Some(Duration::from_millis(100))).unwrap();
+            poll.poll(&mut events, Some(poll_timeout)).unwrap();
             for event in events.iter() {
                 match event.token() {
-                    Token(0) => {
-                        if let Err(e) = self.handle_protocol_event(&event) {
-                            slog::warn!(self.logger, "Failed to process protocol event: {:?}", e);
+                    Token(0) => self.handle_protocol_event(),
+                    Token(2) => {
+                        if let Err(e) = self.handle_push_pull_connection() {
+                            warn!(self.logger, "Failed to synchronise state: {:?}", e);
                         }
                     }
+                    Token(3) => self.handle_exchanges(),
                     Token(1) => match self.receiver.try_recv() {
                         Ok(message) => {
                             debug!(self.logger, "ChannelMessage::{:?}", message);
                             match message {
                                 ChannelMessage::Stop => {
-                                    break 'mainloop;
+                                    if !self.leave() {
+                                        break 'mainloop;
+                                    }
                                 }
                                 ChannelMessage::GetMembers(sender) => {
-                                    let members = std::iter::once(&self.myself.address)
-                                        .chain(self.members.values().map(|m| &m.address))
-                                        .cloned()
+                                    if let Err(e) = sender.send(self.get_members_addresses()) {
+                                        warn!(self.logger, "Failed to send list of members: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::GetMembersWithMetadata(sender) => {
+                                    let members = std::iter::once(&self.myself)
+                                        .chain(self.members.values())
+                                        .map(|m| (m.address, m.metadata.clone()))
                                         .collect::<Vec<_>>();
                                     if let Err(e) = sender.send(members) {
                                         warn!(self.logger, "Failed to send list of members: {:?}", e);
                                     }
                                 }
+                                ChannelMessage::SetMetadata(metadata) => self.update_metadata(metadata),
+                                ChannelMessage::GetDeadMembersCount(sender) => {
+                                    if let Err(e) = sender.send(self.dead_members.len()) {
+                                        warn!(self.logger, "Failed to send number of dead members: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::Broadcast(payload) => self.broadcast_user_message(payload),
+                                ChannelMessage::SubscribeBroadcasts(sender) => self.broadcast_subscribers.push(sender),
+                                ChannelMessage::Subscribe(sender) => {
+                                    self.subscribe(Box::new(move |event| sender.send(event).is_ok()))
+                                }
+                                #[cfg(feature = "tokio")]
+                                ChannelMessage::Call(Call(call)) => call(self),
+                                ChannelMessage::GetMetrics(sender) => {
+                                    if let Err(e) = sender.send(self.metrics.clone()) {
+                                        warn!(self.logger, "Failed to send metrics: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::InstallKey(key, sender) => {
+                                    if let Err(e) = sender.send(self.keyring.install(key)) {
+                                        warn!(self.logger, "Failed to send result of installing key: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::UseKey(key, sender) => {
+                                    if let Err(e) = sender.send(self.keyring.use_key(&key)) {
+                                        warn!(self.logger, "Failed to send result of using key: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::RemoveKey(key, sender) => {
+                                    if let Err(e) = sender.send(self.keyring.remove(&key)) {
+                                        warn!(self.logger, "Failed to send result of removing key: {:?}", e);
+                                    }
+                                }
+                                ChannelMessage::ChangeGroupKeys(operation, key, timeout, sender) => {
+                                    self.change_group_keys(operation, key, timeout, sender)
+                                }
                             }
                         }
                         Err(e) => {
@@ -191,85 +437,214 @@ impl SyncNode {
                 }
             }
 
-            self.handle_acks()?;
+            self.tick()?;
+            if self.has_left() {
+                break;
+            }
+        }
 
-            self.drain_timeout_suspicions()
-                .into_iter()
-                .for_each(|s| self.handle_timeout_suspicion(&s));
+        Ok(())
+    }
 
-            let now = std::time::Instant::now();
-            if now > (last_epoch_time + Duration::from_secs(self.config.protocol_period)) {
-                //                self.show_metrics();
-                debug!(self.logger, "Notifications: {:?}", self.notifications);
-                debug!(self.logger, "Broadcast: {:?}", self.broadcast);
+    /// Handles everything that is due at the current time and sends the messages it produced.
+    pub(crate) fn tick(&mut self) -> Result<()> {
+        self.handle_acks()?;
 
-                self.advance_epoch();
-                last_epoch_time = now;
-            }
+        self.drain_timeout_suspicions()
+            .into_iter()
+            .for_each(|s| self.handle_timeout_suspicion(&s));
+
+        let now = self.now();
+        if now > (self.last_epoch_time + self.probe_interval()) {
+            //                self.show_metrics();
+            debug!(self.logger, "Notifications: {:?}", self.notifications);
+            debug!(self.logger, "Broadcast: {:?}", self.broadcast);
+
+            self.advance_epoch();
+            self.reap_dead_members();
+            self.forget_delivered_user_messages();
+            self.last_epoch_time = now;
+        }
 
-            self.handle_timeouts();
+        let push_pull_interval = self.config.push_pull_interval;
+        if push_pull_interval > Duration::from_secs(0) && now > (self.last_push_pull_time + push_pull_interval) {
+            self.push_pull_random_member();
+            self.last_push_pull_time = now;
         }
 
+        self.handle_timeouts();
+        self.send_requests();
         Ok(())
     }
 
+    /// Returns whether the member was leaving the group and the time to spread the news has passed.
+    pub(crate) fn has_left(&self) -> bool {
+        self.leave_deadline.is_some_and(|deadline| self.now() >= deadline)
+    }
+
+    /// Starts spreading the information that this member is leaving the group.
+    ///
+    /// Returns `false` if there is no one to inform and the member can stop right away. Member which has not heard
+    /// back from the seed yet might already be known to it, so it waits for the answer to spread the information.
+    pub(crate) fn leave(&mut self) -> bool {
+        if (self.members.is_empty() && self.join.is_none()) || self.leave_deadline.is_some() {
+            return false;
+        }
+        info!(self.logger, "Leaving the group");
+        self.add_notification(Notification::Leave {
+            member: self.myself.clone(),
+        });
+        self.announce_leave();
+        self.leave_deadline = Some(self.now() + self.config.leave_timeout);
+        true
+    }
+
+    /// Pings random members right away, the Leave notification should be spread before this member stops.
+    fn announce_leave(&mut self) {
+        let members = self
+            .ping_order
+            .iter()
+            .cloned()
+            .choose_multiple(&mut self.rng, self.config.num_indirect as usize);
+        for member_id in members {
+            let ping = Request::Ping(Header {
+                member_id,
+                sequence_number: self.get_next_sequence_number(),
+            });
+            self.requests.push_back(ping);
+        }
+    }
+
     fn handle_acks(&mut self) -> Result<()> {
-        let now = std::time::Instant::now();
-        let ack_timeout = Duration::from_secs(self.config.ack_timeout as u64);
-        let (handle, postpone): (Vec<_>, Vec<_>) = self
-            .acks
-            .drain(..)
-            .partition(|ack| ack.request_time + ack_timeout <= now);
+        let now = self.now();
+        let ack_timeout = self.local_health.scale(self.config.ack_timeout);
+        // Indirect probes are given the rest of the protocol period, as proxies need to wait for the probed member
+        // themselves before they can relay an ack or send a nack.
+        let indirect_timeout = std::cmp::max(self.probe_interval().saturating_sub(ack_timeout), ack_timeout);
+        let (handle, postpone): (Vec<_>, Vec<_>) = self.acks.drain(..).partition(|ack| {
+            let timeout = match ack.request {
+                Request::PingIndirect(_) => indirect_timeout,
+                _ => ack_timeout,
+            };
+            ack.request_time + timeout <= now
+        });
         handle.into_iter().try_for_each(|ack| self.handle_timeout_ack(ack))?;
         self.acks = postpone;
         Ok(())
     }
 
+    fn probe_interval(&self) -> Duration {
+        self.local_health.scale(self.config.protocol_period)
+    }
+
+    fn increase_local_health(&mut self) {
+        self.local_health.increase();
+        debug!(
+            self.logger,
+            "Local health score increased to {}",
+            self.local_health.score()
+        );
+    }
+
+    fn decrease_local_health(&mut self) {
+        self.local_health.decrease();
+        debug!(
+            self.logger,
+            "Local health score decreased to {}",
+            self.local_health.score()
+        );
+    }
+
     fn handle_timeouts(&mut self) {
-        let now = std::time::Instant::now();
+        let now = self.now();
         let (handle, postpone): (Vec<_>, Vec<_>) = self.timeouts.drain(..).partition(|t| t.when <= now);
         handle.into_iter().for_each(|t| (t.what)(self));
         self.timeouts = postpone;
     }
 
     fn drain_timeout_suspicions(&mut self) -> Vec<Suspicion> {
-        let mut suspicions = Vec::new();
-        loop {
-            match self.suspicions.front() {
-                Some(ref suspicion) => {
-                    if std::time::Instant::now()
-                        > (suspicion.created + Duration::from_secs(self.config.suspect_timeout as u64))
-                    {
-                        suspicions.push(self.suspicions.pop_front().unwrap());
-                    } else {
-                        break;
-                    }
-                }
-                None => break,
-            }
-        }
+        // Timeouts shrink with confirmations, so suspicions are not ordered by their deadlines.
+        let now = self.now();
+        let (suspicions, pending): (Vec<_>, Vec<_>) = self
+            .suspicions
+            .drain(..)
+            .partition(|suspicion| now > suspicion.deadline());
+        self.suspicions = VecDeque::from(pending);
         suspicions
     }
 
-    pub(crate) fn join(&mut self, member: SocketAddr) -> Result<()> {
-        assert_ne!(member, self.myself.address, "Can't join yourself");
-        self.requests.push_front(Request::Init(member));
+    /// Joins the group through any of the `seeds`, notifying `joined` about members of the group once it happens.
+    pub(crate) fn join(&mut self, seeds: Vec<SocketAddr>, joined: Option<Joined>) -> Result<()> {
+        self.begin_join(seeds, joined)?;
         self.start()
     }
 
+    /// Queues the first join request, it is sent once the member starts handling its requests.
+    pub(crate) fn begin_join(&mut self, seeds: Vec<SocketAddr>, joined: Option<Joined>) -> Result<()> {
+        if seeds.is_empty() {
+            return Err(JoinError::NoSeeds.into());
+        }
+        assert!(!seeds.contains(&self.myself.address), "Can't join yourself");
+        self.join = Some(Join {
+            seeds,
+            remaining: Vec::new(),
+            attempt: 0,
+            joined,
+            synchronising: false,
+        });
+        self.join_next_seed()
+    }
+
+    fn finish_join(&mut self) {
+        if let Some(joined) = self.join.take().and_then(|join| join.joined) {
+            joined(self.get_members_addresses());
+        }
+    }
+
+    /// Sends join request to the next seed member, starting a new attempt once all of them have been tried.
+    fn join_next_seed(&mut self) -> Result<()> {
+        let join = self.join.as_mut().unwrap();
+        if let Some(seed) = join.remaining.pop() {
+            self.requests.push_front(Request::Init(seed));
+            return Ok(());
+        }
+        if join.attempt >= self.config.join_max_attempts {
+            return Err(JoinError::NoSeedAnswered { attempts: join.attempt }.into());
+        }
+        join.attempt += 1;
+        join.remaining = join.seeds.clone();
+        join.remaining.shuffle(&mut self.rng);
+        let seed = join.remaining.pop().unwrap();
+        if join.attempt == 1 {
+            self.requests.push_front(Request::Init(seed));
+        } else {
+            let backoff = 2u32.pow(std::cmp::min(join.attempt - 2, 16));
+            info!(self.logger, "Retrying to join the group, attempt {}", join.attempt);
+            self.timeouts.push(Timeout {
+                when: self.now() + self.config.join_retry_timeout * backoff,
+                what: Box::new(move |myself| myself.requests.push_front(Request::Init(seed))),
+            });
+        }
+        Ok(())
+    }
+
     fn handle_timeout_suspicion(&mut self, suspicion: &Suspicion) {
         // Check if the `suspicion` is in notifications. Assume that if it is not then
         // the member has already been moved to a different state and this `suspicion` can be dropped.
-        let position = self
+        let suspect = self
             .notifications
             .iter()
-            .position(|n| n.is_suspect() && *n.member() == suspicion.member);
-        if let Some(position) = position {
-            self.notifications.remove(position);
-            self.notifications.add(Notification::Confirm {
-                member: self.members[&suspicion.member.id].clone(),
-            });
-            self.handle_confirm(&self.members[&suspicion.member.id].clone())
+            .find(|n| n.is_suspect() && *n.member() == suspicion.member)
+            .cloned();
+        if let Some(suspect) = suspect {
+            while self.notifications.remove_item(&suspect).is_some() {}
+            match self.members.get(&suspicion.member.id).cloned() {
+                Some(member) => {
+                    self.notifications.add(Notification::Confirm { member: member.clone() });
+                    self.handle_confirm(&member)
+                }
+                None => debug!(self.logger, "Member {} already removed.", suspicion.member.id),
+            }
         } else {
             debug!(self.logger, "Member {} already removed.", suspicion.member.id);
         }
@@ -291,18 +666,30 @@ impl SyncNode {
         match ack.request {
             Request::Init(address) => {
                 info!(self.logger, "Failed to join {}", address);
-                self.timeouts.push(Timeout {
-                    when: std::time::Instant::now() + std::time::Duration::from_secs(self.config.join_retry_timeout),
-                    what: Box::new(|myself| myself.requests.push_front(ack.request)),
-                });
+                self.join_next_seed()?;
             }
             Request::Ping(header) => {
+                self.increase_local_health();
                 self.requests.push_back(Request::PingIndirect(header));
             }
             Request::PingIndirect(header) => {
-                self.handle_suspect_other(&self.members.get(&header.member_id).unwrap().clone());
+                // Missing nacks mean that the indirect probes themselves got lost, which suggests that the problem
+                // is on this member's side rather than the probed one.
+                let missed_nacks = ack.expected_nacks.saturating_sub(ack.nacks);
+                if missed_nacks > 0 {
+                    info!(
+                        self.logger,
+                        "Missed {} of {} nacks for {}", missed_nacks, ack.expected_nacks, header.member_id
+                    );
+                }
+                (0..missed_nacks).for_each(|_| self.increase_local_health());
+                // The member might have been removed in the meantime, e.g. by a Confirm notification from others.
+                if let Some(member) = self.members.get(&header.member_id).cloned() {
+                    self.handle_suspect_other(&member, self.myself.id);
+                }
             }
             Request::PingProxy(request) => {
+                // Nack has already been sent, see `nack_ping_proxy`.
                 warn!(
                     self.logger,
                     "Ping proxy from {} to {} timed out", request.sender.id, request.target.id
@@ -314,38 +701,236 @@ impl SyncNode {
     }
 
     fn bind(&mut self, poll: &Poll) -> Result<()> {
-        self.udp = Some(UdpSocket::bind(&self.myself.address).context("Failed to bind UDP socket")?);
-        // FIXME: change to `PollOpt::edge()`
+        let default_transport = self.transport.is_none();
+        if default_transport {
+            let udp = UdpSocket::bind(&self.myself.address).context("Failed to bind UDP socket")?;
+            self.transport = Some(Box::new(udp));
+        }
+        let registry = Registry::new(poll, Token(0));
+        self.transport
+            .as_deref_mut()
+            .unwrap()
+            .register(&registry)
+            .map_err(|e| format_err!("Failed to register transport for polling: {:?}", e))?;
+        self.transport_readiness = registry.into_readiness();
+        if !default_transport {
+            // Custom transport does not have to be backed by the host network at all.
+            info!(
+                self.logger,
+                "Push-pull synchronisation is disabled with custom transport"
+            );
+            return Ok(());
+        }
+        let tcp = std::net::TcpListener::bind(self.myself.address).context("Failed to bind TCP socket")?;
+        self.tcp = Some(TcpListener::from_std(tcp)?);
         poll.register(
-            self.udp.as_ref().unwrap(),
-            Token(0),
-            Ready::readable() | Ready::writable(),
+            self.tcp.as_ref().unwrap(),
+            Token(2),
+            Ready::readable(),
             PollOpt::level(),
         )
-        .map_err(|e| format_err!("Failed to register UDP socket for polling: {:?}", e))
+        .map_err(|e| format_err!("Failed to register TCP socket for polling: {:?}", e))?;
+        poll.register(&self.exchange_receiver, Token(3), Ready::readable(), PollOpt::empty())
+            .map_err(|e| format_err!("Failed to register push-pull channel for polling: {:?}", e))
+    }
+
+    fn push_pull_random_member(&mut self) {
+        let address = self.ping_order.choose(&mut self.rng).map(|id| self.members[id].address);
+        if let Some(address) = address {
+            if let Err(e) = self.push_pull(address) {
+                warn!(self.logger, "Failed to synchronise state with {}: {:?}", address, e);
+            }
+        }
+    }
+
+    /// Starts exchanging entire state of the group with member at `address` (push-pull from memberlist).
+    ///
+    /// The connection is handled by a worker thread, received state is merged once it reports back. Returns whether
+    /// the exchange has started, it is disabled when this member has no TCP socket.
+    fn push_pull(&mut self, address: SocketAddr) -> Result<bool> {
+        if self.tcp.is_none() {
+            debug!(
+                self.logger,
+                "Push-pull synchronisation is disabled, not synchronising with {}", address
+            );
+            return Ok(false);
+        }
+        debug!(self.logger, "Synchronising state with {}", address);
+        // The member might not be known yet when joining.
+        let version = self
+            .members
+            .values()
+            .find(|m| m.address == address)
+            .map_or(self.unknown_member_version(), version_for);
+        let state = self.seal(self.encode_state(version)?.buffer()).into_owned();
+        let timeout = self.local_health.scale(self.config.ack_timeout);
+        let max_size = push_pull::max_frame_size(self.members.len());
+        self.push_pull_workers.initiate(address, state, timeout, max_size)?;
+        Ok(true)
+    }
+
+    fn handle_push_pull_connection(&mut self) -> Result<()> {
+        let (stream, address) = match self.tcp.as_ref().unwrap().accept_std() {
+            Ok(connection) => connection,
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        debug!(self.logger, "Synchronising state with {}", address);
+        let timeout = self.local_health.scale(self.config.ack_timeout);
+        let max_size = push_pull::max_frame_size(self.members.len());
+        self.push_pull_workers.respond(stream, address, timeout, max_size)
+    }
+
+    fn handle_exchanges(&mut self) {
+        while let Ok(exchange) = self.exchange_receiver.try_recv() {
+            let address = exchange.address;
+            let initiated = exchange.reply.is_none();
+            if let Err(e) = self.handle_exchange(exchange) {
+                warn!(self.logger, "Failed to synchronise state with {}: {:?}", address, e);
+            }
+            if initiated && self.join.as_ref().is_some_and(|join| join.synchronising) {
+                self.finish_join();
+            }
+        }
+    }
+
+    /// Merges state received by a worker, it is decoded only once it has been authenticated and decrypted.
+    fn handle_exchange(&mut self, exchange: push_pull::Exchange) -> Result<()> {
+        let buffer = exchange.state?;
+        let buffer = self.open(&buffer).inspect_err(|e| self.count_rejected(e))?;
+        let state = decode_push_pull_message(&buffer, self.cluster).inspect_err(|e| self.count_rejected(e))?;
+        if let Some(reply) = exchange.reply {
+            let buffer = self
+                .seal(self.encode_state(version_for(&state.sender))?.buffer())
+                .into_owned();
+            reply
+                .send(buffer)
+                .map_err(|_| format_err!("Connection has been closed before replying"))?;
+        }
+        self.merge_state(&state);
+        Ok(())
+    }
+
+    fn encode_state(&self, version: u8) -> Result<OutgoingMessage> {
+        let members = self
+            .members
+            .values()
+            .map(
+                |member| match self.suspicions.iter().find(|s| s.member.id == member.id) {
+                    Some(suspicion) if suspicion.member.incarnation >= member.incarnation => Notification::Suspect {
+                        member: suspicion.member.clone(),
+                        from: self.myself.id,
+                    },
+                    _ => Notification::Alive { member: member.clone() },
+                },
+            )
+            .collect::<Vec<_>>();
+        Ok(PushPullMessageEncoder::new(MAX_PUSH_PULL_SIZE, version, self.cluster)
+            .sender(&self.myself)?
+            .members(&members)?
+            .encode())
+    }
+
+    fn merge_state(&mut self, state: &PushPullMessageIn) {
+        if let Some(ref mut delegate) = self.delegate {
+            let members = state
+                .members
+                .iter()
+                .map(|n| n.member().into())
+                .collect::<Vec<MemberInfo>>();
+            if !delegate.allow_merge(&(&state.sender).into(), &members) {
+                info!(self.logger, "Delegate refused to merge state of {:?}", state.sender);
+                return;
+            }
+        }
+        self.update_member(&state.sender);
+        for notification in &state.members {
+            let member = notification.member();
+            match self.members.get(&member.id) {
+                None if !notification.is_suspect() => self.update_member(member),
+                Some(known) if known.incarnation >= member.incarnation && !notification.is_suspect() => {}
+                _ => self.process_notifications(std::iter::once(notification)),
+            }
+        }
+    }
+
+    /// Encrypts the encoded message with the primary key and signs it with the secret key, if they are set.
+    fn seal<'b>(&self, buffer: &'b [u8]) -> Cow<'b, [u8]> {
+        let mut buffer = Cow::Borrowed(buffer);
+        if !self.keyring.is_empty() {
+            buffer = Cow::Owned(self.keyring.encrypt(&buffer));
+        }
+        if let Some(ref key) = self.config.secret_key {
+            buffer = Cow::Owned(authentication::sign(key, &buffer));
+        }
+        buffer
+    }
+
+    /// Reverses `seal`, failing if the message was not signed or encrypted with the expected keys.
+    fn open<'b>(&self, buffer: &'b [u8]) -> Result<Cow<'b, [u8]>> {
+        let buffer = match self.config.secret_key {
+            Some(ref key) => authentication::verify(key, buffer)?,
+            None => buffer,
+        };
+        if self.keyring.is_empty() {
+            Ok(Cow::Borrowed(buffer))
+        } else {
+            Ok(Cow::Owned(self.keyring.decrypt(buffer)?))
+        }
+    }
+
+    fn count_rejected(&mut self, error: &failure::Error) {
+        if error.downcast_ref::<AuthenticationError>().is_some() {
+            self.metrics.unauthenticated_messages += 1;
+        } else if error.downcast_ref::<DecryptionError>().is_some() {
+            self.metrics.undecryptable_messages += 1;
+        } else if error.downcast_ref::<ForeignClusterError>().is_some() {
+            self.metrics.foreign_cluster_messages += 1;
+        }
     }
 
     fn send_message(&mut self, target: SocketAddr, message: OutgoingMessage) {
         debug!(self.logger, "{:?} <- {:?}", target, message);
-        match self.udp.as_ref().unwrap().send_to(message.buffer(), &target) {
+        let buffer = self.seal(message.buffer());
+        match self.transport.as_ref().unwrap().send_to(&buffer, target) {
             Err(e) => warn!(self.logger, "Message to {:?} was not delivered due to {:?}", target, e),
             Ok(count) => {
                 debug!(self.logger, "Send {} bytes", count);
                 if let OutgoingMessage::DisseminationMessage(ref dissemination_message) = message {
                     self.notifications.mark(dissemination_message.num_notifications());
                     self.broadcast.mark(dissemination_message.num_broadcast());
+                    self.user_messages.mark(dissemination_message.num_user_messages());
+                    self.key_messages.mark(dissemination_message.num_key_messages());
                 }
             }
         }
     }
 
     fn recv_letter(&mut self) -> Option<IncomingLetter> {
-        match self.udp.as_ref().unwrap().recv_from(&mut self.recv_buffer) {
+        // Readiness of custom transport is cleared before receiving, so that no datagram arriving later is missed,
+        // and it is set again as long as there might be more of them.
+        let readiness = self.transport_readiness.as_ref().map(|(_, readiness)| readiness);
+        if let Some(readiness) = readiness {
+            let _ = readiness.clear();
+        }
+        match self.transport.as_ref().unwrap().recv_from(&mut self.recv_buffer) {
             Ok((count, sender)) => {
+                if let Some(readiness) = readiness {
+                    let _ = readiness.set_readable();
+                }
                 debug!(self.logger, "Received {} bytes from {:?}", count, sender);
-                let message = match decode_message(&self.recv_buffer[..count]) {
+                let buffer = match self.open(&self.recv_buffer[..count]) {
+                    Ok(buffer) => buffer,
+                    Err(e) => {
+                        self.count_rejected(&e);
+                        warn!(self.logger, "Dropped message from {:?}: {}", sender, e);
+                        return None;
+                    }
+                };
+                let message = match decode_message(&buffer, self.cluster) {
                     Ok(message) => message,
                     Err(e) => {
+                        self.count_rejected(&e);
                         warn!(self.logger, "Failed to decode from message {:#?}: {}", sender, e);
                         return None;
                     }
@@ -354,6 +939,7 @@ impl SyncNode {
                 debug!(self.logger, "{:?}", letter);
                 Some(letter)
             }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
             Err(e) => {
                 warn!(self.logger, "Failed to receive letter due to {:?}", e);
                 None
@@ -368,6 +954,11 @@ impl SyncNode {
     }
 
     fn update_member(&mut self, member: &Member) {
+        self.update_member_as(member, MembershipEvent::Updated);
+    }
+
+    /// Adds `member` or updates the known one, reporting the update of a known member with `event`.
+    fn update_member_as(&mut self, member: &Member, event: fn(MemberInfo) -> MembershipEvent) {
         if member.id == self.myself.id {
             return;
         }
@@ -375,33 +966,73 @@ impl SyncNode {
         // instance has died.
         if member.address == self.myself.address {
             warn!(self.logger, "Trying to add myself but with wrong ID {:?}", member);
+            if let Some(ref mut delegate) = self.delegate {
+                delegate.notify_conflict(&(&self.myself).into(), &member.into());
+            }
             return;
         }
         if let Some(m) = self.members.get_mut(&member.id) {
             if m.incarnation < member.incarnation {
                 m.incarnation = member.incarnation;
+                m.metadata = member.metadata.clone();
+                self.emit(event(member.into()));
             }
             return;
         }
-        if self.dead_members.contains(&member.id) {
+        if self.dead_members.contains_key(&member.id) {
             info!(self.logger, "Member {:?} has already been marked as dead", member);
             return;
         }
+        if protocol_version::negotiate(member).is_none() {
+            warn!(
+                self.logger,
+                "Member {:?} does not speak any version of the protocol this member does", member
+            );
+            return;
+        }
+        if let Some(ref mut delegate) = self.delegate {
+            if !delegate.allow_join(&member.into()) {
+                info!(self.logger, "Delegate refused to add member {:?}", member);
+                return;
+            }
+            if let Some(existing) = self.members.values().find(|m| m.address == member.address) {
+                delegate.notify_conflict(&existing.into(), &member.into());
+            }
+        }
         self.members.insert(member.id, member.clone());
         self.ping_order.push(member.id);
-        self.broadcast.add(member.id);
+        let limit = self.dissemination_limit();
+        self.broadcast.add_with_limit(member.id, limit);
         info!(self.logger, "Member joined: {:?}", member);
+        self.emit(MembershipEvent::Joined(member.into()));
+    }
+
+    /// Adds `subscriber` of membership events.
+    pub(crate) fn subscribe(&mut self, subscriber: EventSubscriber) {
+        self.event_subscribers.push(subscriber);
+    }
+
+    /// Delivers `event` to all subscribers, forgetting those that are gone.
+    fn emit(&mut self, event: MembershipEvent) {
+        self.event_subscribers.retain(|subscriber| subscriber(event.clone()));
     }
 
     fn process_notifications<'m>(&mut self, notifications: impl Iterator<Item = &'m Notification>) {
         for notification in notifications {
+            if let Notification::Suspect { member, from } = notification {
+                if self.confirm_suspicion(member, *from) {
+                    self.requeue_suspect(notification.clone());
+                    continue;
+                }
+            }
             if self.notifications.iter().find(|&n| n >= notification).is_some() {
                 continue;
             }
             match notification {
                 Notification::Confirm { member } => self.handle_confirm(member),
                 Notification::Alive { member } => self.handle_alive(member),
-                Notification::Suspect { member } => self.handle_suspect(member),
+                Notification::Suspect { member, from } => self.handle_suspect(member, *from),
+                Notification::Leave { member } => self.handle_leave(member),
             }
             let obsolete_notifications = self
                 .notifications
@@ -429,18 +1060,47 @@ impl SyncNode {
         if notification.is_suspect() {
             self.notifications.add(notification);
         } else {
-            self.notifications
-                .add_with_limit(notification, self.config.notification_dissemination_times);
+            let limit = self.dissemination_limit();
+            self.notifications.add_with_limit(notification, limit);
         }
     }
 
     fn handle_confirm(&mut self, member: &Member) {
         self.remove_suspicion(member);
-        self.dead_members.insert(member.id);
-        self.remove_member(&member.id);
+        self.dead_members.insert(member.id, self.now());
+        self.remove_member(&member.id, MembershipEvent::Dead);
         // TODO: start spreading Confirm notification
     }
 
+    fn handle_leave(&mut self, member: &Member) {
+        if member.id == self.myself.id {
+            return;
+        }
+        info!(self.logger, "Member {:?} is leaving the group", member);
+        self.remove_suspicion(member);
+        // The member does not answer probes once it stops, their timeouts must not count against this one.
+        self.acks.retain(|ack| match ack.request {
+            Request::Ping(ref header) | Request::PingIndirect(ref header) => header.member_id != member.id,
+            _ => true,
+        });
+        self.dead_members.insert(member.id, self.now());
+        self.remove_member(&member.id, MembershipEvent::Left);
+    }
+
+    pub(crate) fn get_members_addresses(&self) -> Vec<SocketAddr> {
+        std::iter::once(&self.myself.address)
+            .chain(self.members.values().map(|m| &m.address))
+            .cloned()
+            .collect()
+    }
+
+    fn reap_dead_members(&mut self) {
+        let retention = self.config.dead_member_retention;
+        let now = self.now();
+        self.dead_members
+            .retain(|_, confirmed| now.duration_since(*confirmed) < retention);
+    }
+
     fn remove_suspicion(&mut self, member: &Member) {
         if let Some(position) = self.suspicions.iter().position(|s| s.member == *member) {
             self.suspicions.remove(position);
@@ -448,21 +1108,31 @@ impl SyncNode {
     }
 
     fn handle_alive(&mut self, member: &Member) {
+        let refuted = self
+            .suspicions
+            .iter()
+            .any(|s| s.member.id == member.id && s.member.incarnation < member.incarnation);
         self.remove_suspicion(member);
-        self.update_member(member);
+        if refuted {
+            self.update_member_as(member, MembershipEvent::Alive);
+        } else {
+            self.update_member(member);
+        }
     }
 
-    fn handle_suspect(&mut self, member: &Member) {
+    fn handle_suspect(&mut self, member: &Member, from: MemberId) {
         if member.id == self.myself.id {
             self.handle_suspect_myself(member);
         } else {
-            self.handle_suspect_other(member);
+            // Newer incarnation is applied first, so the suspicion is reported after the update.
             self.update_member(member);
+            self.handle_suspect_other(member, from);
         }
     }
 
     fn handle_suspect_myself(&mut self, suspect: &Member) {
         if self.myself.incarnation <= suspect.incarnation {
+            self.increase_local_health();
             self.myself.incarnation = suspect.incarnation + 1;
             info!(
                 self.logger,
@@ -474,14 +1144,33 @@ impl SyncNode {
         }
     }
 
-    fn handle_suspect_other(&mut self, suspect: &Member) {
+    fn update_metadata(&mut self, metadata: Vec<u8>) {
+        self.myself.metadata = metadata;
+        self.myself.incarnation += 1;
+        info!(
+            self.logger,
+            "Metadata changed, increasing my incarnation to {}", self.myself.incarnation
+        );
+        self.add_notification(Notification::Alive {
+            member: self.myself.clone(),
+        });
+    }
+
+    fn handle_suspect_other(&mut self, suspect: &Member, from: MemberId) {
+        // The member might have been refused by the delegate or be already dead.
+        if !self.members.contains_key(&suspect.id) {
+            debug!(self.logger, "Not suspecting unknown member {:?}", suspect);
+            return;
+        }
         // FIXME: Might be inefficient to check entire deq
         match self.suspicions.iter().position(|s| s.member.id == suspect.id) {
             Some(idx) if self.suspicions[idx].member.incarnation >= suspect.incarnation => {
-                info!(
-                    self.logger,
-                    "Member {:?} is already suspected", self.suspicions[idx].member
-                );
+                let member = self.suspicions[idx].member.clone();
+                if self.confirm_suspicion(&member, from) {
+                    self.requeue_suspect(Notification::Suspect { member, from });
+                } else {
+                    info!(self.logger, "Member {:?} is already suspected", member);
+                }
             }
             Some(idx) => {
                 info!(
@@ -489,20 +1178,77 @@ impl SyncNode {
                     "Member {:?} suspected with lower incarnation, replacing it", self.suspicions[idx].member
                 );
                 self.suspicions.remove(idx);
-                self.suspect_member(suspect)
+                self.suspect_member(suspect, from)
             }
-            None => self.suspect_member(suspect),
+            None => self.suspect_member(suspect, from),
         }
     }
 
-    fn suspect_member(&mut self, suspect: &Member) {
+    /// Records that `from` suspects the member too, returns `true` if it is a new confirmation of a suspicion.
+    fn confirm_suspicion(&mut self, suspect: &Member, from: MemberId) -> bool {
+        if let Some(suspicion) = self.suspicions.iter_mut().find(|s| s.member == *suspect) {
+            if suspicion.confirm(from) {
+                debug!(
+                    self.logger,
+                    "Suspicion of {:?} confirmed by {}, timeout is now {:?}",
+                    suspect,
+                    from,
+                    suspicion.timeout()
+                );
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Replaces the disseminated Suspect notification of the same member with `suspect`.
+    ///
+    /// Following Lifeguard, every independent confirmation is spread further, so that other members suspecting
+    /// the member can count it as well and shorten their timeouts.
+    fn requeue_suspect(&mut self, suspect: Notification) {
+        while self.notifications.remove_item(&suspect).is_some() {}
+        self.add_notification(suspect);
+    }
+
+    fn suspect_member(&mut self, suspect: &Member, from: MemberId) {
         info!(self.logger, "Start suspecting member {:?}", suspect);
         let member = suspect.clone();
-        self.suspicions.push_back(Suspicion::new(suspect.clone()));
-        self.add_notification(Notification::Suspect { member });
+        self.suspicions.push_back(self.create_suspicion(suspect.clone(), from));
+        self.add_notification(Notification::Suspect { member, from });
+        self.emit(MembershipEvent::Suspected(suspect.into()));
+    }
+
+    fn create_suspicion(&self, suspect: Member, from: MemberId) -> Suspicion {
+        // Following Lifeguard, the minimal timeout grows with logarithm of the group size and there is no point
+        // in waiting for more confirmations than there are members that could send them.
+        let group_size = self.members.len() + 1;
+        let min_timeout = self
+            .config
+            .suspect_timeout
+            .mul_f64(f64::max(1.0, (group_size as f64).log10()));
+        let max_timeout = min_timeout * self.config.suspect_max_timeout_multiplier;
+        let expected_confirmations = if group_size.saturating_sub(2) < self.config.suspect_confirmations as usize {
+            0
+        } else {
+            self.config.suspect_confirmations
+        };
+        Suspicion::new(
+            suspect,
+            from,
+            self.now(),
+            min_timeout,
+            max_timeout,
+            expected_confirmations,
+        )
+    }
+
+    /// Number of times information is disseminated, `λ·log(n)` following SWIM.
+    fn dissemination_limit(&self) -> u64 {
+        let group_size = self.members.len() + 1;
+        self.config.dissemination_multiplier * ((group_size + 1) as f64).log10().ceil() as u64
     }
 
-    fn remove_member(&mut self, member_id: &MemberId) {
+    fn remove_member(&mut self, member_id: &MemberId, event: fn(MemberInfo) -> MembershipEvent) {
         match self.members.remove(member_id) {
             Some(removed_member) => {
                 let idx = self.ping_order.iter().position(|e| e == member_id).unwrap();
@@ -512,6 +1258,7 @@ impl SyncNode {
                     self.next_member_index -= 1;
                 }
                 info!(self.logger, "Member removed: {:?}", removed_member);
+                self.emit(event((&removed_member).into()));
             }
             None => debug!(self.logger, "Trying to remove unknown member {:?}", member_id),
         }
@@ -533,106 +1280,170 @@ impl SyncNode {
         Some(target)
     }
 
+    /// Randomly selects members to probe `target` indirectly, skipping members that are suspected.
+    fn select_indirect_members(&mut self, target: &MemberId) -> Vec<MemberId> {
+        let suspicions = &self.suspicions;
+        self.ping_order
+            .iter()
+            .filter(|&id| id != target && !suspicions.iter().any(|s| s.member.id == *id))
+            .cloned()
+            .choose_multiple(&mut self.rng, self.config.num_indirect as usize)
+    }
+
     fn get_next_sequence_number(&mut self) -> u64 {
         let sequence_number = self.sequence_number;
         self.sequence_number += 1;
         sequence_number
     }
 
-    fn handle_protocol_event(&mut self, event: &Event) -> Result<()> {
-        if event.readiness().is_readable() {
-            if let Some(letter) = self.recv_letter() {
-                match letter.message {
-                    IncomingMessage::Ping(m) => self.handle_ping(&m),
-                    IncomingMessage::Ack(m) => self.handle_ack(&m),
-                    IncomingMessage::PingRequest(m) => self.handle_indirect_ping(&m),
-                }
+    pub(crate) fn handle_protocol_event(&mut self) {
+        if let Some(letter) = self.recv_letter() {
+            match letter.message {
+                IncomingMessage::Ping(m) => self.handle_ping(&m),
+                IncomingMessage::Ack(m) => self.handle_ack(&m),
+                IncomingMessage::PingRequest(m) => self.handle_indirect_ping(&m),
+                IncomingMessage::Nack(m) => self.handle_nack(&m),
             }
-        } else if event.readiness().is_writable() {
-            if let Some(request) = self.requests.pop_front() {
-                debug!(self.logger, "{:?}", request);
-                match request {
-                    Request::Init(address) => {
-                        let message = DisseminationMessageEncoder::new(1024)
-                            .message_type(MessageType::Ping)?
-                            .sender(&self.myself)?
-                            .sequence_number(0)?
-                            .encode();
-                        self.send_message(address, message);
-                        self.acks.push(Ack::new(request));
-                    }
-                    Request::Ping(ref header) if self.members.contains_key(&header.member_id) => {
-                        let message = DisseminationMessageEncoder::new(1024)
-                            .message_type(MessageType::Ping)?
-                            .sender(&self.myself)?
-                            .sequence_number(header.sequence_number)?
-                            .notifications(self.notifications.iter())?
-                            .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
-                            .encode();
-                        self.send_message(self.members[&header.member_id].address, message);
-                        self.acks.push(Ack::new(request));
-                    }
-                    Request::Ping(ref header) => {
-                        info!(
-                            self.logger,
-                            "Dropping Ping message, member {} has already been removed.", header.member_id
-                        );
-                    }
-                    Request::PingIndirect(ref header) if self.members.contains_key(&header.member_id) => {
-                        let indirect_members = self
-                            .members
-                            .keys()
-                            .filter(|&key| *key != header.member_id)
-                            .take(self.config.num_indirect as usize)
-                            .cloned()
-                            .collect::<Vec<_>>();
-                        indirect_members.iter().try_for_each(|member_id| -> Result<()> {
-                            let message = PingRequestMessageEncoder::new()
-                                .sender(&self.myself)?
-                                .sequence_number(header.sequence_number)?
-                                .target(&self.members[&header.member_id])?
-                                .encode();
-                            self.send_message(self.members[member_id].address, message);
-                            Ok(())
-                        })?;
-                        self.acks.push(Ack::new(request));
-                    }
-                    Request::PingIndirect(ref header) => {
-                        info!(
-                            self.logger,
-                            "Dropping PingIndirect message, member {} has already been removed.", header.member_id
-                        );
-                    }
-                    Request::PingProxy(ref ping_proxy) => {
-                        let message = DisseminationMessageEncoder::new(1024)
-                            .message_type(MessageType::Ping)?
-                            .sender(&self.myself)?
-                            .sequence_number(ping_proxy.sequence_number)?
-                            .notifications(self.notifications.iter())?
-                            .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
-                            .encode();
-                        self.send_message(ping_proxy.target.address, message);
-                        self.acks.push(Ack::new(request));
-                    }
-                    Request::Ack(header) => {
-                        let message = DisseminationMessageEncoder::new(1024)
-                            .message_type(MessageType::PingAck)?
-                            .sender(&self.myself)?
-                            .sequence_number(header.sequence_number)?
-                            .notifications(self.notifications.iter())?
-                            .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
-                            .encode();
-                        self.send_message(self.members[&header.member_id].address, message);
-                    }
-                    Request::AckIndirect(ack_indirect) => {
-                        let message = DisseminationMessageEncoder::new(1024)
-                            .message_type(MessageType::PingAck)?
-                            .sender(&self.myself)?
-                            .sequence_number(ack_indirect.sequence_number)?
-                            .encode();
-                        self.send_message(ack_indirect.target.address, message);
-                    }
-                }
+        }
+    }
+
+    /// Sends messages for all pending requests, datagrams do not wait for the transport to become writable.
+    fn send_requests(&mut self) {
+        while let Some(request) = self.requests.pop_front() {
+            if let Err(e) = self.send_request(request) {
+                warn!(self.logger, "Failed to send request: {:?}", e);
+            }
+        }
+    }
+
+    /// Returns version of the protocol to speak with members whose versions are not known yet.
+    ///
+    /// The oldest version is the safest guess, unless the group is named, as its name is only carried by newer ones.
+    fn unknown_member_version(&self) -> u8 {
+        if self.cluster == protocol_version::DEFAULT_CLUSTER {
+            protocol_version::MIN_VERSION
+        } else {
+            protocol_version::CLUSTER_VERSION
+        }
+    }
+
+    fn send_request(&mut self, request: Request) -> Result<()> {
+        debug!(self.logger, "{:?}", request);
+        match request {
+            Request::Init(address) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(self.unknown_member_version())
+                    .cluster(self.cluster)
+                    .message_type(MessageType::Ping)?
+                    .sender(&self.myself)?
+                    .sequence_number(0)?
+                    .encode();
+                self.send_message(address, message);
+                self.acks.push(Ack::new(request, self.now()));
+            }
+            Request::Ping(ref header) if self.members.contains_key(&header.member_id) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(version_for(&self.members[&header.member_id]))
+                    .cluster(self.cluster)
+                    .message_type(MessageType::Ping)?
+                    .sender(&self.myself)?
+                    .sequence_number(header.sequence_number)?
+                    .notifications(self.notifications.iter())?
+                    .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
+                    .user_messages(self.user_messages.iter())?
+                    .key_messages(self.key_messages.iter())?
+                    .delegate_data(|limit| self.piggyback_data(limit))?
+                    .encode();
+                self.send_message(self.members[&header.member_id].address, message);
+                self.acks.push(Ack::new(request, self.now()));
+            }
+            Request::Ping(ref header) => {
+                info!(
+                    self.logger,
+                    "Dropping Ping message, member {} has already been removed.", header.member_id
+                );
+            }
+            Request::PingIndirect(ref header) if self.members.contains_key(&header.member_id) => {
+                let indirect_members = self.select_indirect_members(&header.member_id);
+                indirect_members.iter().try_for_each(|member_id| -> Result<()> {
+                    let message = PingRequestMessageEncoder::new(version_for(&self.members[member_id]), self.cluster)
+                        .sender(&self.myself)?
+                        .sequence_number(header.sequence_number)?
+                        .target(&self.members[&header.member_id])?
+                        .encode();
+                    self.send_message(self.members[member_id].address, message);
+                    Ok(())
+                })?;
+                self.acks
+                    .push(Ack::with_expected_nacks(request, self.now(), indirect_members.len()));
+            }
+            Request::PingIndirect(ref header) => {
+                info!(
+                    self.logger,
+                    "Dropping PingIndirect message, member {} has already been removed.", header.member_id
+                );
+            }
+            Request::PingProxy(ref ping_proxy) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(version_for(&ping_proxy.target))
+                    .cluster(self.cluster)
+                    .message_type(MessageType::Ping)?
+                    .sender(&self.myself)?
+                    .sequence_number(ping_proxy.sequence_number)?
+                    .notifications(self.notifications.iter())?
+                    .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
+                    .user_messages(self.user_messages.iter())?
+                    .key_messages(self.key_messages.iter())?
+                    .delegate_data(|limit| self.piggyback_data(limit))?
+                    .encode();
+                self.send_message(ping_proxy.target.address, message);
+                let (sender, sequence_number) = (ping_proxy.sender.id, ping_proxy.sequence_number);
+                self.timeouts.push(Timeout {
+                    when: self.now() + self.config.ack_timeout.mul_f64(NACK_TIMEOUT_FRACTION),
+                    what: Box::new(move |node| node.nack_ping_proxy(sender, sequence_number)),
+                });
+                self.acks.push(Ack::new(request, self.now()));
+            }
+            Request::Ack(ref header) if self.members.contains_key(&header.member_id) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(version_for(&self.members[&header.member_id]))
+                    .cluster(self.cluster)
+                    .message_type(MessageType::PingAck)?
+                    .sender(&self.myself)?
+                    .sequence_number(header.sequence_number)?
+                    .notifications(self.notifications.iter())?
+                    .broadcast(self.broadcast.iter().map(|id| &self.members[id]))?
+                    .user_messages(self.user_messages.iter())?
+                    .key_messages(self.key_messages.iter())?
+                    .delegate_data(|limit| self.piggyback_data(limit))?
+                    .encode();
+                self.send_message(self.members[&header.member_id].address, message);
+            }
+            Request::Ack(ref header) => {
+                info!(
+                    self.logger,
+                    "Dropping Ack message, member {} has already been removed.", header.member_id
+                );
+            }
+            Request::AckIndirect(ack_indirect) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(version_for(&ack_indirect.target))
+                    .cluster(self.cluster)
+                    .message_type(MessageType::PingAck)?
+                    .sender(&self.myself)?
+                    .sequence_number(ack_indirect.sequence_number)?
+                    .encode();
+                self.send_message(ack_indirect.target.address, message);
+            }
+            Request::NackIndirect(nack_indirect) => {
+                let message = DisseminationMessageEncoder::new(1024)
+                    .version(version_for(&nack_indirect.target))
+                    .cluster(self.cluster)
+                    .message_type(MessageType::PingNack)?
+                    .sender(&self.myself)?
+                    .sequence_number(nack_indirect.sequence_number)?
+                    .encode();
+                self.send_message(nack_indirect.target.address, message);
             }
         }
         Ok(())
@@ -642,21 +1453,232 @@ impl SyncNode {
         self.update_member(&message.sender);
         self.update_members(message.broadcast.iter());
         self.process_notifications(message.notifications.iter());
+        self.process_user_messages(message.user_messages.iter());
+        self.process_key_messages(message.key_messages.iter());
+        if let Some(ref mut delegate) = self.delegate {
+            if !message.delegate_data.is_empty() {
+                delegate.notify_piggyback_data(&(&message.sender).into(), &message.delegate_data);
+            }
+        }
+    }
+
+    /// Returns data the delegate wants to piggyback on a message with `limit` bytes left.
+    fn piggyback_data(&mut self, limit: usize) -> Vec<u8> {
+        self.delegate
+            .as_mut()
+            .map_or_else(Vec::new, |delegate| delegate.piggyback_data(limit))
+    }
+
+    fn broadcast_user_message(&mut self, payload: Vec<u8>) {
+        let message = UserMessage {
+            origin: self.myself.id,
+            sequence_number: self.user_message_sequence_number,
+            payload: payload.into(),
+        };
+        self.user_message_sequence_number += 1;
+        self.delivered_user_messages
+            .insert((message.origin, message.sequence_number), self.now());
+        let limit = self.dissemination_limit();
+        self.user_messages.add_with_limit(message, limit);
+    }
+
+    fn process_user_messages<'m>(&mut self, messages: impl Iterator<Item = &'m UserMessage>) {
+        for message in messages {
+            let key = (message.origin, message.sequence_number);
+            if self.delivered_user_messages.contains_key(&key) {
+                continue;
+            }
+            self.delivered_user_messages.insert(key, self.now());
+            self.broadcast_subscribers
+                .retain(|subscriber| subscriber.send(message.payload.to_vec()).is_ok());
+            if let Some(ref mut delegate) = self.delegate {
+                delegate.notify_user_message(&message.payload);
+            }
+            let limit = self.dissemination_limit();
+            self.user_messages.add_with_limit(message.clone(), limit);
+        }
+    }
+
+    /// Forgets user messages delivered long enough ago that no member disseminates them anymore.
+    ///
+    /// Members which received the message later keep disseminating it after it has been delivered here, so
+    /// it is remembered for twice as many protocol periods as it is disseminated.
+    fn forget_delivered_user_messages(&mut self) {
+        let retention = self.config.protocol_period * 2 * self.dissemination_limit() as u32;
+        let now = self.now();
+        self.delivered_user_messages
+            .retain(|_, delivered| now.duration_since(*delivered) < retention);
+    }
+
+    /// Applies `operation` locally and requests it from all members, reporting their responses after `timeout`.
+    fn change_group_keys(
+        &mut self,
+        operation: KeyOperation,
+        key: Vec<u8>,
+        timeout: Duration,
+        reply: std::sync::mpsc::SyncSender<std::result::Result<KeyReport, KeyringError>>,
+    ) {
+        // Keys must never be sent in plaintext.
+        if self.keyring.is_empty() {
+            if let Err(e) = reply.send(Err(KeyringError::NotEncrypted)) {
+                warn!(self.logger, "Failed to send result of changing keys: {:?}", e);
+            }
+            return;
+        }
+        let mut report = KeyReport::default();
+        match operation.apply(&mut self.keyring, &key) {
+            Ok(()) => report.succeeded.push(self.myself.address),
+            Err(KeyringError::InvalidKeySize(size)) => {
+                if let Err(e) = reply.send(Err(KeyringError::InvalidKeySize(size))) {
+                    warn!(self.logger, "Failed to send result of changing keys: {:?}", e);
+                }
+                return;
+            }
+            Err(e) => report.failed.push((self.myself.address, e)),
+        }
+
+        let sequence_number = self.key_sequence_number;
+        self.key_sequence_number += 1;
+        info!(
+            self.logger,
+            "Requesting {:?} of a key from {} members",
+            operation,
+            self.members.len()
+        );
+        self.key_queries.insert(
+            sequence_number,
+            KeyQuery {
+                key_size: key.len(),
+                pending: self.members.values().map(|m| (m.id, m.address)).collect(),
+                report,
+                reply,
+            },
+        );
+        let request = KeyMessage::Request {
+            origin: self.myself.id,
+            sequence_number,
+            operation,
+            key,
+        };
+        self.handled_key_messages.push(request.id());
+        let limit = self.dissemination_limit();
+        self.key_messages.add_with_limit(request, limit);
+        self.timeouts.push(Timeout {
+            when: self.now() + timeout,
+            what: Box::new(move |node| node.finish_key_query(sequence_number)),
+        });
+        if self.members.is_empty() {
+            self.finish_key_query(sequence_number);
+        }
+    }
+
+    fn finish_key_query(&mut self, sequence_number: u64) {
+        if let Some(query) = self.key_queries.remove(&sequence_number) {
+            let mut report = query.report;
+            report.missing = query.pending.values().cloned().collect();
+            if let Err(e) = query.reply.send(Ok(report)) {
+                warn!(self.logger, "Failed to send result of changing keys: {:?}", e);
+            }
+        }
+    }
+
+    fn process_key_messages<'m>(&mut self, messages: impl Iterator<Item = &'m KeyMessage>) {
+        for message in messages {
+            let id = message.id();
+            if self.handled_key_messages.contains(&id) {
+                continue;
+            }
+            self.handled_key_messages.push(id);
+            let limit = self.dissemination_limit();
+            match message {
+                KeyMessage::Request {
+                    origin,
+                    sequence_number,
+                    operation,
+                    key,
+                } => {
+                    let result = operation.apply(&mut self.keyring, key);
+                    info!(
+                        self.logger,
+                        "{:?} of a key requested by {}: {:?}", operation, origin, result
+                    );
+                    let response = KeyMessage::Response {
+                        origin: *origin,
+                        sequence_number: *sequence_number,
+                        member: self.myself.id,
+                        result,
+                    };
+                    self.handled_key_messages.push(response.id());
+                    self.key_messages.add_with_limit(message.clone(), limit);
+                    self.key_messages.add_with_limit(response, limit);
+                }
+                KeyMessage::Response {
+                    origin,
+                    sequence_number,
+                    member,
+                    result,
+                } if *origin == self.myself.id => self.record_key_response(*sequence_number, member, result),
+                KeyMessage::Response { .. } => self.key_messages.add_with_limit(message.clone(), limit),
+            }
+        }
+    }
+
+    fn record_key_response(
+        &mut self,
+        sequence_number: u64,
+        member: &MemberId,
+        result: &std::result::Result<(), KeyringError>,
+    ) {
+        let query = match self.key_queries.get_mut(&sequence_number) {
+            Some(query) => query,
+            None => return,
+        };
+        if let Some(address) = query.pending.remove(member) {
+            match result {
+                Ok(()) => query.report.succeeded.push(address),
+                Err(KeyringError::InvalidKeySize(_)) => query
+                    .report
+                    .failed
+                    .push((address, KeyringError::InvalidKeySize(query.key_size))),
+                Err(e) => query.report.failed.push((address, e.clone())),
+            }
+        }
+        if query.pending.is_empty() {
+            self.finish_key_query(sequence_number);
+        }
     }
 
     fn handle_ack(&mut self, message: &DisseminationMessageIn) {
+        self.update_state(message);
         for ack in self.acks.drain(..).collect::<Vec<_>>() {
             match ack.request {
                 Request::Init(address) => {
-                    self.update_state(message);
-                    if message.sender.address != address || message.sequence_number != 0 {
-                        panic!("Initial ping request failed, unable to continue");
+                    if message.sender.address == address && message.sequence_number == 0 {
+                        info!(self.logger, "Joined the group through {}", address);
+                        if self.leave_deadline.is_some() {
+                            self.announce_leave();
+                        }
+                        // Members are reported once they are learnt from the state of the seed.
+                        match self.push_pull(address) {
+                            Ok(true) => {
+                                if let Some(ref mut join) = self.join {
+                                    join.synchronising = true;
+                                }
+                            }
+                            Ok(false) => self.finish_join(),
+                            Err(e) => {
+                                warn!(self.logger, "Failed to synchronise state with {}: {:?}", address, e);
+                                self.finish_join();
+                            }
+                        }
+                        continue;
                     }
-                    continue;
                 }
                 Request::PingIndirect(ref header) => {
-                    self.update_state(message);
-                    if message.sender.id == header.member_id && message.sequence_number == header.sequence_number {
+                    // Indirect ack comes from one of the proxies, not from the probed member, thus only sequence
+                    // number can be matched.
+                    if message.sequence_number == header.sequence_number {
+                        self.decrease_local_health();
                         continue;
                     }
                 }
@@ -672,8 +1694,8 @@ impl SyncNode {
                     }
                 }
                 Request::Ping(ref header) => {
-                    self.update_state(message);
                     if message.sender.id == header.member_id && message.sequence_number == header.sequence_number {
+                        self.decrease_local_health();
                         continue;
                     }
                 }
@@ -683,6 +1705,20 @@ impl SyncNode {
         }
     }
 
+    fn handle_nack(&mut self, message: &DisseminationMessageIn) {
+        let ack = self.acks.iter_mut().find(|ack| match ack.request {
+            Request::PingIndirect(ref header) => header.sequence_number == message.sequence_number,
+            _ => false,
+        });
+        match ack {
+            Some(ack) => ack.nacks += 1,
+            None => debug!(
+                self.logger,
+                "Nack from {} does not match any request", message.sender.id
+            ),
+        }
+    }
+
     fn handle_ping(&mut self, message: &DisseminationMessageIn) {
         self.update_state(message);
         self.requests.push_back(Request::Ack(Header {
@@ -691,6 +1727,27 @@ impl SyncNode {
         }));
     }
 
+    /// Sends nack to the requester of the indirect probe if the probed member has not responded yet.
+    ///
+    /// Unlike the wait for the ack itself, the nack is not delayed by local health of this member, otherwise
+    /// an unhealthy proxy would always miss the deadline of the requester.
+    fn nack_ping_proxy(&mut self, sender: MemberId, sequence_number: u64) {
+        let request = self.acks.iter().find_map(|ack| match ack.request {
+            Request::PingProxy(ref ping_proxy)
+                if ping_proxy.sender.id == sender && ping_proxy.sequence_number == sequence_number =>
+            {
+                Some(AckIndirectRequest {
+                    target: ping_proxy.sender.clone(),
+                    sequence_number,
+                })
+            }
+            _ => None,
+        });
+        if let Some(request) = request {
+            self.requests.push_back(Request::NackIndirect(request));
+        }
+    }
+
     fn handle_indirect_ping(&mut self, message: &PingRequestMessageIn) {
         self.requests.push_back(Request::PingProxy(PingProxyRequest {
             sender: message.sender.clone(),
@@ -699,3 +1756,803 @@ impl SyncNode {
         }));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ututils::create_members;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    fn create_node() -> SyncNode {
+        let (node, _) = SyncNode::new(SocketAddr::from_str("127.0.0.1:2345").unwrap(), Default::default());
+        node
+    }
+
+    #[test]
+    fn select_indirect_members_uniformly() {
+        let mut node = create_node();
+        let members = create_members(10);
+        node.update_members(members.iter());
+        let target = members[0].id;
+        let suspected = members[1].id;
+        node.suspicions
+            .push_back(node.create_suspicion(members[1].clone(), node.myself.id));
+
+        let rounds = 10000;
+        let mut counts = HashMap::new();
+        for _ in 0..rounds {
+            let selected = node.select_indirect_members(&target);
+            assert_eq!(selected.len(), node.config.num_indirect as usize);
+            selected.into_iter().for_each(|id| *counts.entry(id).or_insert(0) += 1);
+        }
+
+        assert!(!counts.contains_key(&target));
+        assert!(!counts.contains_key(&suspected));
+        assert_eq!(counts.len(), members.len() - 2);
+        // Each of 8 eligible members is expected to be selected in 3/8 of rounds, allow 10% deviation.
+        let expected = rounds as f64 * node.config.num_indirect as f64 / counts.len() as f64;
+        for count in counts.values() {
+            assert!(
+                (*count as f64 - expected).abs() < expected * 0.1,
+                "{} vs {}",
+                count,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn match_indirect_ack_relayed_by_proxy() {
+        let mut node = create_node();
+        let members = create_members(2);
+        node.update_members(members.iter());
+        node.acks.push(Ack::new(
+            Request::PingIndirect(Header {
+                member_id: members[0].id,
+                sequence_number: 7,
+            }),
+            Instant::now(),
+        ));
+
+        // The proxy relays the ack of the probed member as its own.
+        node.handle_ack(&DisseminationMessageIn {
+            sender: members[1].clone(),
+            sequence_number: 7,
+            notifications: vec![],
+            broadcast: vec![],
+            user_messages: vec![],
+            key_messages: vec![],
+            delegate_data: vec![],
+        });
+
+        assert!(node.acks.is_empty());
+    }
+
+    #[test]
+    fn nack_from_slow_proxy_arrives_before_indirect_timeout() {
+        let clock = Clock::new_virtual();
+        let mut requester = create_node();
+        requester.set_clock(clock.clone());
+        let (mut proxy, _) = SyncNode::new(SocketAddr::from_str("127.0.0.1:2346").unwrap(), Default::default());
+        proxy.set_clock(clock.clone());
+        proxy.set_transport(Box::new(
+            UdpSocket::bind(&SocketAddr::from_str("127.0.0.1:0").unwrap()).unwrap(),
+        ));
+        (0..10).for_each(|_| proxy.increase_local_health());
+        let target = create_members(1).remove(0);
+        requester.update_member(&target);
+        requester.acks.push(Ack::with_expected_nacks(
+            Request::PingIndirect(Header {
+                member_id: target.id,
+                sequence_number: 7,
+            }),
+            clock.now(),
+            1,
+        ));
+
+        proxy.handle_indirect_ping(&PingRequestMessageIn {
+            sender: requester.myself.clone(),
+            sequence_number: 7,
+            target: target.clone(),
+        });
+        proxy.send_requests();
+        clock.advance(proxy.config.ack_timeout.mul_f64(NACK_TIMEOUT_FRACTION));
+        proxy.handle_timeouts();
+        proxy.handle_acks().unwrap();
+        match proxy.requests.pop_front() {
+            Some(Request::NackIndirect(request)) => {
+                assert_eq!(request.target.id, requester.myself.id);
+                assert_eq!(request.sequence_number, 7);
+            }
+            request => panic!("Expected nack, got {:?}", request),
+        }
+
+        requester.handle_acks().unwrap();
+        assert_eq!(requester.acks.len(), 1);
+        requester.handle_nack(&DisseminationMessageIn {
+            sender: proxy.myself.clone(),
+            sequence_number: 7,
+            notifications: vec![],
+            broadcast: vec![],
+            user_messages: vec![],
+            key_messages: vec![],
+            delegate_data: vec![],
+        });
+        clock.advance(requester.config.protocol_period);
+        requester.handle_acks().unwrap();
+        assert!(requester.acks.is_empty());
+        assert_eq!(requester.local_health.score(), 0);
+    }
+
+    #[test]
+    fn push_pull_does_not_block_protocol() -> Result<()> {
+        let mut node = create_node();
+        node.tcp = Some(TcpListener::from_std(std::net::TcpListener::bind("127.0.0.1:0")?)?);
+        // Connection is accepted by the system, but the member never responds.
+        let unresponsive = std::net::TcpListener::bind("127.0.0.1:0")?;
+
+        let start = Instant::now();
+        assert!(node.push_pull(unresponsive.local_addr()?)?);
+        assert!(start.elapsed() < node.config.ack_timeout);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_state() {
+        let mut node = create_node();
+        let members = create_members(3);
+        node.update_member(&members[0]);
+        let mut refuted = members[0].clone();
+        refuted.incarnation += 1;
+
+        node.merge_state(&PushPullMessageIn {
+            sender: members[1].clone(),
+            members: vec![
+                Notification::Alive { member: refuted },
+                Notification::Suspect {
+                    member: members[2].clone(),
+                    from: members[1].id,
+                },
+                Notification::Alive {
+                    member: node.myself.clone(),
+                },
+            ],
+        });
+
+        assert_eq!(node.members.len(), 3);
+        assert_eq!(node.members[&members[0].id].incarnation, 1);
+        assert_eq!(node.suspicions.len(), 1);
+        assert_eq!(node.suspicions[0].member, members[2]);
+    }
+
+    #[test]
+    fn update_metadata_with_incarnation() {
+        let mut node = create_node();
+        let members = create_members(1);
+        node.update_member(&members[0]);
+        let mut stale = members[0].clone();
+        stale.metadata = b"stale".to_vec();
+        node.update_member(&stale);
+        assert!(node.members[&members[0].id].metadata.is_empty());
+
+        let mut changed = members[0].clone();
+        changed.incarnation += 1;
+        changed.metadata = b"changed".to_vec();
+        node.update_member(&changed);
+        assert_eq!(node.members[&members[0].id].metadata, b"changed".to_vec());
+    }
+
+    #[test]
+    fn emit_membership_events() {
+        let mut node = create_node();
+        let members = create_members(2);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        node.subscribe(Box::new(move |event| sender.send(event).is_ok()));
+        let mut refuted = members[0].clone();
+        refuted.incarnation += 1;
+        let mut updated = refuted.clone();
+        updated.incarnation += 1;
+
+        node.update_members(members.iter());
+        let notifications = [
+            Notification::Suspect {
+                member: members[0].clone(),
+                from: members[1].id,
+            },
+            Notification::Alive {
+                member: refuted.clone(),
+            },
+            Notification::Alive {
+                member: updated.clone(),
+            },
+            Notification::Confirm {
+                member: updated.clone(),
+            },
+            Notification::Leave {
+                member: members[1].clone(),
+            },
+        ];
+        node.process_notifications(notifications.iter());
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                MembershipEvent::Joined((&members[0]).into()),
+                MembershipEvent::Joined((&members[1]).into()),
+                MembershipEvent::Suspected((&members[0]).into()),
+                MembershipEvent::Alive((&refuted).into()),
+                MembershipEvent::Updated((&updated).into()),
+                MembershipEvent::Dead((&updated).into()),
+                MembershipEvent::Left((&members[1]).into()),
+            ]
+        );
+    }
+
+    #[derive(Default)]
+    struct RecordingDelegate {
+        refuse: bool,
+        calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Delegate for RecordingDelegate {
+        fn notify_piggyback_data(&mut self, _sender: &MemberInfo, data: &[u8]) {
+            self.calls.lock().unwrap().push(format!("data {:?}", data));
+        }
+
+        fn notify_user_message(&mut self, payload: &[u8]) {
+            self.calls.lock().unwrap().push(format!("message {:?}", payload));
+        }
+
+        fn allow_join(&mut self, _member: &MemberInfo) -> bool {
+            !self.refuse
+        }
+
+        fn allow_merge(&mut self, _sender: &MemberInfo, _members: &[MemberInfo]) -> bool {
+            !self.refuse
+        }
+
+        fn notify_conflict(&mut self, existing: &MemberInfo, other: &MemberInfo) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("conflict {} {}", existing.address, other.address));
+        }
+    }
+
+    #[test]
+    fn update_state_once_for_pending_acks() {
+        let mut node = create_node();
+        let delegate = RecordingDelegate::default();
+        let calls = delegate.calls.clone();
+        node.set_delegate(Box::new(delegate));
+        let members = create_members(2);
+        node.update_members(members.iter());
+        node.acks.push(Ack::new(
+            Request::Ping(Header {
+                member_id: members[0].id,
+                sequence_number: 7,
+            }),
+            Instant::now(),
+        ));
+        node.acks.push(Ack::new(
+            Request::PingIndirect(Header {
+                member_id: members[1].id,
+                sequence_number: 8,
+            }),
+            Instant::now(),
+        ));
+
+        node.handle_ack(&DisseminationMessageIn {
+            sender: members[0].clone(),
+            sequence_number: 7,
+            notifications: vec![],
+            broadcast: vec![],
+            user_messages: vec![],
+            key_messages: vec![],
+            delegate_data: b"d".to_vec(),
+        });
+
+        assert_eq!(*calls.lock().unwrap(), vec![format!("data {:?}", b"d")]);
+        assert_eq!(node.acks.len(), 1);
+    }
+
+    #[test]
+    fn dont_suspect_refused_member() {
+        let mut node = create_node();
+        node.set_delegate(Box::new(RecordingDelegate {
+            refuse: true,
+            ..Default::default()
+        }));
+        let members = create_members(2);
+
+        node.handle_suspect(&members[0], members[1].id);
+
+        assert!(node.suspicions.is_empty());
+        assert_eq!(node.notifications.iter().count(), 0);
+    }
+
+    #[test]
+    fn timeout_suspicion_of_removed_member() {
+        let mut node = create_node();
+        let members = create_members(2);
+        node.update_members(members.iter());
+        node.handle_suspect(&members[0], members[1].id);
+        node.members.remove(&members[0].id);
+
+        let suspicion = node.suspicions.pop_front().unwrap();
+        node.handle_timeout_suspicion(&suspicion);
+
+        assert_eq!(node.notifications.iter().count(), 0);
+    }
+
+    #[test]
+    fn refuse_members_by_delegate() {
+        let mut node = create_node();
+        node.set_delegate(Box::new(RecordingDelegate {
+            refuse: true,
+            ..Default::default()
+        }));
+        let members = create_members(2);
+
+        node.update_member(&members[0]);
+        node.merge_state(&PushPullMessageIn {
+            sender: members[1].clone(),
+            members: vec![Notification::Alive {
+                member: members[0].clone(),
+            }],
+        });
+
+        assert!(node.members.is_empty());
+    }
+
+    #[test]
+    fn notify_delegate() {
+        let mut node = create_node();
+        let delegate = RecordingDelegate::default();
+        let calls = delegate.calls.clone();
+        node.set_delegate(Box::new(delegate));
+        let members = create_members(1);
+        let mut restarted = members[0].clone();
+        restarted.id = std::convert::TryFrom::try_from([9u8; 20].as_ref()).unwrap();
+
+        node.update_state(&DisseminationMessageIn {
+            sender: members[0].clone(),
+            sequence_number: 0,
+            notifications: Vec::new(),
+            broadcast: vec![restarted],
+            user_messages: vec![UserMessage {
+                origin: members[0].id,
+                sequence_number: 0,
+                payload: bytes::Bytes::from_static(b"m"),
+            }],
+            key_messages: Vec::new(),
+            delegate_data: b"d".to_vec(),
+        });
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "conflict 127.0.0.1:1234 127.0.0.1:1234".to_string(),
+                format!("message {:?}", b"m"),
+                format!("data {:?}", b"d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reap_dead_members_after_retention() {
+        let mut node = create_node();
+        node.config.dead_member_retention = Duration::from_secs(60);
+        let members = create_members(2);
+        node.update_members(members.iter());
+        node.handle_confirm(&members[0]);
+        node.handle_leave(&members[1]);
+        node.dead_members
+            .insert(members[1].id, Instant::now() - Duration::from_secs(61));
+
+        node.reap_dead_members();
+
+        assert!(node.dead_members.contains_key(&members[0].id));
+        assert!(!node.dead_members.contains_key(&members[1].id));
+        node.update_member(&members[0]);
+        node.update_member(&members[1]);
+        assert_eq!(node.members.keys().collect::<Vec<_>>(), vec![&members[1].id]);
+    }
+
+    #[test]
+    fn open_sealed_message() -> Result<()> {
+        let mut node = create_node();
+        node.config.secret_key = Some(b"secret".to_vec());
+        node.keyring.install(vec![1u8; 32])?;
+
+        let sealed = node.seal(b"message");
+        assert_ne!(&sealed[..7], b"message");
+        assert_eq!(node.open(&sealed)?.as_ref(), b"message");
+        Ok(())
+    }
+
+    #[test]
+    fn count_rejected_messages() -> Result<()> {
+        let mut node = create_node();
+        node.keyring.install(vec![1u8; 32])?;
+        let sealed = node.seal(b"message").into_owned();
+
+        node.config.secret_key = Some(b"secret".to_vec());
+        let error = node.open(&sealed).err().unwrap();
+        node.count_rejected(&error);
+        node.config.secret_key = None;
+        node.keyring.install(vec![2u8; 32])?;
+        node.keyring.use_key(&[2u8; 32])?;
+        node.keyring.remove(&[1u8; 32])?;
+        let error = node.open(&sealed).err().unwrap();
+        node.count_rejected(&error);
+
+        assert_eq!(node.metrics.unauthenticated_messages, 1);
+        assert_eq!(node.metrics.undecryptable_messages, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn join_fails_after_max_attempts() {
+        let mut node = create_node();
+        node.config.join_max_attempts = 2;
+        let seeds = create_members(2).into_iter().map(|m| m.address).collect::<Vec<_>>();
+        node.join = Some(Join {
+            seeds: seeds.clone(),
+            remaining: Vec::new(),
+            attempt: 0,
+            joined: None,
+            synchronising: false,
+        });
+
+        node.join_next_seed().unwrap();
+        let mut tried = Vec::new();
+        for _ in 0..seeds.len() {
+            match node.requests.pop_front() {
+                Some(Request::Init(seed)) => {
+                    tried.push(seed);
+                    node.handle_timeout_ack(Ack::new(Request::Init(seed), Instant::now()))
+                        .unwrap();
+                }
+                request => panic!("Unexpected request {:?}", request),
+            }
+        }
+        assert_eq!(
+            tried.iter().collect::<HashSet<_>>(),
+            seeds.iter().collect::<HashSet<_>>()
+        );
+        assert!(node.requests.is_empty());
+        assert_eq!(node.timeouts.len(), 1);
+
+        (node.timeouts.pop().unwrap().what)(&mut node);
+        let seed = match node.requests.pop_front() {
+            Some(Request::Init(seed)) => seed,
+            request => panic!("Unexpected request {:?}", request),
+        };
+        node.handle_timeout_ack(Ack::new(Request::Init(seed), Instant::now()))
+            .unwrap();
+        let seed = match node.requests.pop_front() {
+            Some(Request::Init(seed)) => seed,
+            request => panic!("Unexpected request {:?}", request),
+        };
+        let error = node
+            .handle_timeout_ack(Ack::new(Request::Init(seed), Instant::now()))
+            .unwrap_err();
+        match error.downcast::<JoinError>() {
+            Ok(JoinError::NoSeedAnswered { attempts }) => assert_eq!(attempts, 2),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn keep_join_request_on_unexpected_ack() {
+        let mut node = create_node();
+        let members = create_members(2);
+        node.acks
+            .push(Ack::new(Request::Init(members[0].address), Instant::now()));
+
+        node.handle_ack(&DisseminationMessageIn {
+            sender: members[1].clone(),
+            sequence_number: 0,
+            notifications: Vec::new(),
+            broadcast: Vec::new(),
+            user_messages: Vec::new(),
+            key_messages: Vec::new(),
+            delegate_data: Vec::new(),
+        });
+
+        assert_eq!(node.acks.len(), 1);
+    }
+
+    #[test]
+    fn announce_leave_once_joined() -> Result<()> {
+        let mut node = create_node();
+        let seed = create_members(1).remove(0);
+        node.begin_join(vec![seed.address], None)?;
+        node.requests.clear();
+        node.acks.push(Ack::new(Request::Init(seed.address), Instant::now()));
+
+        // The seed might already know this member, even though it has not answered yet.
+        assert!(node.leave());
+        assert!(node.requests.is_empty());
+        node.handle_ack(&DisseminationMessageIn {
+            sender: seed.clone(),
+            sequence_number: 0,
+            notifications: Vec::new(),
+            broadcast: Vec::new(),
+            user_messages: Vec::new(),
+            key_messages: Vec::new(),
+            delegate_data: Vec::new(),
+        });
+
+        match node.requests.pop_front() {
+            Some(Request::Ping(header)) => assert_eq!(header.member_id, seed.id),
+            request => panic!("Expected ping, got {:?}", request),
+        }
+        assert!(node.notifications.iter().any(|n| match n {
+            Notification::Leave { member } => member.id == node.myself.id,
+            _ => false,
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn drop_probes_of_leaving_member() {
+        let mut node = create_node();
+        let members = create_members(1);
+        node.update_members(members.iter());
+        node.acks.push(Ack::new(
+            Request::Ping(Header {
+                member_id: members[0].id,
+                sequence_number: 1,
+            }),
+            Instant::now(),
+        ));
+
+        node.process_notifications(std::iter::once(&Notification::Leave {
+            member: members[0].clone(),
+        }));
+
+        assert!(node.members.is_empty());
+        assert!(node.acks.is_empty());
+    }
+
+    #[test]
+    fn apply_and_answer_key_request() {
+        let mut node = create_node();
+        node.keyring.install(vec![1u8; 32]).unwrap();
+        let origin = create_members(1).pop().unwrap();
+        let request = KeyMessage::Request {
+            origin: origin.id,
+            sequence_number: 0,
+            operation: KeyOperation::Install,
+            key: vec![2u8; 32],
+        };
+
+        node.process_key_messages(std::iter::once(&request));
+        node.process_key_messages(std::iter::once(&request));
+
+        assert!(node.keyring.use_key(&[2u8; 32]).is_ok());
+        let response = KeyMessage::Response {
+            origin: origin.id,
+            sequence_number: 0,
+            member: node.myself.id,
+            result: Ok(()),
+        };
+        assert_eq!(node.key_messages.iter().collect::<Vec<_>>(), vec![&response, &request]);
+    }
+
+    #[test]
+    fn report_key_responses() {
+        let mut node = create_node();
+        node.keyring.install(vec![1u8; 32]).unwrap();
+        let members = create_members(3);
+        node.update_members(members.iter());
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+
+        node.change_group_keys(KeyOperation::Remove, vec![2u8; 32], Duration::from_secs(60), sender);
+        for (member, result) in members[..2].iter().zip(vec![Ok(()), Err(KeyringError::PrimaryKey)]) {
+            node.process_key_messages(std::iter::once(&KeyMessage::Response {
+                origin: node.myself.id,
+                sequence_number: 0,
+                member: member.id,
+                result,
+            }));
+        }
+        assert!(receiver.try_recv().is_err());
+        (node.timeouts.pop().unwrap().what)(&mut node);
+
+        let report = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(report.succeeded, vec![members[0].address]);
+        assert_eq!(
+            report.failed,
+            vec![
+                (node.myself.address, KeyringError::UnknownKey),
+                (members[1].address, KeyringError::PrimaryKey)
+            ]
+        );
+        assert_eq!(report.missing, vec![members[2].address]);
+    }
+
+    #[test]
+    fn refuse_to_send_keys_in_plaintext() {
+        let mut node = create_node();
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        node.change_group_keys(KeyOperation::Install, vec![1u8; 32], Duration::from_secs(60), sender);
+        assert_eq!(receiver.try_recv().unwrap().err(), Some(KeyringError::NotEncrypted));
+        assert_eq!(node.key_messages.iter().count(), 0);
+    }
+
+    #[test]
+    fn dissemination_limit_grows_with_group() {
+        let mut node = create_node();
+        node.config.dissemination_multiplier = 4;
+        assert_eq!(node.dissemination_limit(), 4);
+
+        node.update_members(create_members(8).iter());
+        assert_eq!(node.dissemination_limit(), 4);
+
+        node.update_members(create_members(9).iter().skip(8));
+        assert_eq!(node.dissemination_limit(), 8);
+
+        node.update_members(create_members(150).iter().skip(9));
+        assert_eq!(node.dissemination_limit(), 12);
+    }
+
+    #[test]
+    fn ignore_member_without_common_version() {
+        let mut node = create_node();
+        let mut members = create_members(2);
+        members[0].min_version = protocol_version::MAX_VERSION + 1;
+        members[0].max_version = protocol_version::MAX_VERSION + 1;
+        members[1].max_version = protocol_version::MAX_VERSION + 1;
+
+        node.update_members(members.iter());
+
+        assert_eq!(node.members.keys().collect::<Vec<_>>(), vec![&members[1].id]);
+    }
+
+    #[test]
+    fn speak_version_1_with_old_member() -> Result<()> {
+        let mut node = create_node();
+        node.set_transport(Box::new(UdpSocket::bind(&SocketAddr::from_str("127.0.0.1:0")?)?));
+        let old = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        old.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut member = Member::new(old.local_addr()?);
+        member.max_version = 1;
+        node.update_member(&member);
+
+        node.requests.push_back(Request::Ping(Header {
+            member_id: member.id,
+            sequence_number: 1,
+        }));
+        node.send_requests();
+        let mut buffer = [0u8; 1500];
+        let (length, _) = old.recv_from(&mut buffer)?;
+        // Header of version 1 does not carry the cluster.
+        assert_eq!(&buffer[..3], &[protocol_version::MAGIC, 1, MessageType::Ping as u8]);
+        assert!(decode_message(&buffer[..length], protocol_version::DEFAULT_CLUSTER).is_ok());
+
+        let ack = DisseminationMessageEncoder::new(1024)
+            .version(1)
+            .message_type(MessageType::PingAck)?
+            .sender(&member)?
+            .sequence_number(1)?
+            .encode();
+        match decode_message(ack.buffer(), node.cluster)? {
+            IncomingMessage::Ack(message) => node.handle_ack(&message),
+            message => panic!("Unexpected message {:?}", message),
+        }
+        assert!(node.acks.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn confirm_suspicion_with_failed_probe() {
+        let mut node = create_node();
+        let members = create_members(3);
+        node.update_members(members.iter());
+        node.process_notifications(std::iter::once(&Notification::Suspect {
+            member: members[0].clone(),
+            from: members[1].id,
+        }));
+
+        node.handle_suspect_other(&members[0], node.myself.id);
+        node.process_notifications(std::iter::once(&Notification::Suspect {
+            member: members[0].clone(),
+            from: members[2].id,
+        }));
+
+        assert_eq!(node.suspicions.len(), 1);
+        assert_eq!(node.suspicions[0].confirmations(), 2);
+        // Only the latest confirmation is spread further, replacing the previous ones.
+        let suspects = node
+            .notifications
+            .iter()
+            .filter_map(|n| match n {
+                Notification::Suspect { from, .. } => Some(*from),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(suspects, vec![members[2].id]);
+    }
+
+    #[test]
+    fn select_indirect_members_from_small_group() {
+        let mut node = create_node();
+        let members = create_members(2);
+        node.update_members(members.iter());
+
+        assert_eq!(node.select_indirect_members(&members[0].id), vec![members[1].id]);
+    }
+
+    #[test]
+    fn deliver_user_message_once() {
+        let mut node = create_node();
+        let members = create_members(1);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        node.broadcast_subscribers.push(sender);
+        let message = UserMessage {
+            origin: members[0].id,
+            sequence_number: 0,
+            payload: bytes::Bytes::from_static(b"payload"),
+        };
+
+        node.process_user_messages(std::iter::once(&message));
+        node.process_user_messages(std::iter::once(&message));
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![b"payload".to_vec()]);
+        assert_eq!(node.user_messages.iter().count(), 1);
+    }
+
+    #[test]
+    fn deliver_user_message_once_while_many_others_are_delivered() {
+        let mut node = create_node();
+        let members = create_members(1);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        node.broadcast_subscribers.push(sender);
+        let messages = (0..2000)
+            .map(|sequence_number| UserMessage {
+                origin: members[0].id,
+                sequence_number,
+                payload: bytes::Bytes::from_static(b"payload"),
+            })
+            .collect::<Vec<_>>();
+
+        node.process_user_messages(messages.iter());
+        node.forget_delivered_user_messages();
+        node.process_user_messages(messages.iter().take(1));
+
+        assert_eq!(receiver.try_iter().count(), messages.len());
+    }
+
+    #[test]
+    fn forget_delivered_user_messages_after_dissemination() {
+        let mut node = create_node();
+        let members = create_members(1);
+        let retention = node.config.protocol_period * 2 * node.dissemination_limit() as u32;
+        node.delivered_user_messages
+            .insert((members[0].id, 0), Instant::now() - retention);
+        node.delivered_user_messages
+            .insert((members[0].id, 1), Instant::now() - retention / 2);
+
+        node.forget_delivered_user_messages();
+
+        assert!(!node.delivered_user_messages.contains_key(&(members[0].id, 0)));
+        assert!(node.delivered_user_messages.contains_key(&(members[0].id, 1)));
+    }
+
+    #[test]
+    fn dont_deliver_own_user_message() {
+        let mut node = create_node();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        node.broadcast_subscribers.push(sender);
+        node.broadcast_user_message(b"payload".to_vec());
+        let message = node.user_messages.iter().next().unwrap().clone();
+
+        node.process_user_messages(std::iter::once(&message));
+
+        assert!(receiver.try_recv().is_err());
+    }
+}
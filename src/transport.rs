@@ -0,0 +1,97 @@
+#![deny(missing_docs)]
+
+use mio::net::UdpSocket;
+use mio::{Evented, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use std::cell::RefCell;
+use std::io;
+use std::net::SocketAddr;
+
+/// Carries datagrams of the protocol between members, UDP socket is used by default.
+///
+/// The protocol is woken up when the transport becomes readable, see `register`. Datagrams are sent without waiting
+/// for the transport to become writable, the ones that can not be sent are treated as lost. Push-pull synchronisation
+/// of the state of the group needs TCP, so it is disabled for members using other transports than the default one,
+/// they learn about the group only through gossip.
+pub trait Transport: Send {
+    /// Registers the transport before the protocol starts receiving datagrams.
+    ///
+    /// The transport keeps the `Readiness` returned by `registry` and marks it readable whenever a datagram can be
+    /// received, the protocol clears it itself. Transports that are never polled, e.g. in simulations, do nothing.
+    fn register(&mut self, registry: &Registry) -> io::Result<()>;
+
+    /// Sends `buffer` as a single datagram to `target`, returning the number of bytes sent.
+    fn send_to(&self, buffer: &[u8], target: SocketAddr) -> io::Result<usize>;
+
+    /// Receives a single datagram into `buffer`, returning its size and the address of the sender.
+    ///
+    /// Fails with `WouldBlock` when there is no datagram to receive.
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+}
+
+impl Transport for UdpSocket {
+    fn register(&mut self, registry: &Registry) -> io::Result<()> {
+        registry.register_evented(self)
+    }
+
+    fn send_to(&self, buffer: &[u8], target: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buffer, &target)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buffer)
+    }
+}
+
+/// Registers a transport to be polled together with other sources of events of the protocol.
+pub struct Registry<'a> {
+    poll: &'a Poll,
+    token: Token,
+    readiness: RefCell<Option<(Registration, Readiness)>>,
+}
+
+impl<'a> Registry<'a> {
+    pub(crate) fn new(poll: &'a Poll, token: Token) -> Self {
+        Registry {
+            poll,
+            token,
+            readiness: RefCell::new(None),
+        }
+    }
+
+    /// Returns readiness of the transport, which wakes up the protocol once it is marked readable.
+    pub fn readiness(&self) -> io::Result<Readiness> {
+        let mut readiness = self.readiness.borrow_mut();
+        if readiness.is_none() {
+            let (registration, set_readiness) = Registration::new2();
+            self.register_evented(&registration)?;
+            *readiness = Some((registration, Readiness(set_readiness)));
+        }
+        Ok(readiness.as_ref().unwrap().1.clone())
+    }
+
+    pub(crate) fn register_evented(&self, evented: &impl Evented) -> io::Result<()> {
+        // FIXME: change to `PollOpt::edge()`
+        self.poll
+            .register(evented, self.token, Ready::readable(), PollOpt::level())
+    }
+
+    /// Returns readiness handed out to the transport, the registration has to be kept as long as it is polled.
+    pub(crate) fn into_readiness(self) -> Option<(Registration, Readiness)> {
+        self.readiness.into_inner()
+    }
+}
+
+/// Wakes up the protocol when the transport has datagrams to receive.
+#[derive(Clone, Debug)]
+pub struct Readiness(SetReadiness);
+
+impl Readiness {
+    /// Marks the transport readable, the protocol receives datagrams until `recv_from` fails with `WouldBlock`.
+    pub fn set_readable(&self) -> io::Result<()> {
+        self.0.set_readiness(Ready::readable())
+    }
+
+    pub(crate) fn clear(&self) -> io::Result<()> {
+        self.0.set_readiness(Ready::empty())
+    }
+}
@@ -33,14 +33,19 @@ impl<T> UniqueCircularBuffer<T> {
             .filter(|&(_, e)| *e == *el)
             .map(|(idx, _)| idx)
             .collect::<Vec<_>>();
-        let mut round: usize = 0;
-        for idx in &indices {
+        for (round, idx) in indices.iter().enumerate() {
             self.buffer.remove(idx - round);
-            round += 1;
         }
         indices.len()
     }
 
+    pub fn contains(&self, el: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.buffer.contains(el)
+    }
+
     pub fn len(&self) -> usize {
         self.buffer.len()
     }
@@ -113,6 +118,20 @@ mod test {
         assert_eq!(as_vec(&buffer), [5, 4, 2]);
     }
 
+    #[test]
+    fn contains() {
+        let mut buffer = UniqueCircularBuffer::new(2);
+        assert!(!buffer.contains(&1));
+
+        buffer.push(1);
+        assert!(buffer.contains(&1));
+
+        buffer.push(2);
+        buffer.push(3);
+        assert!(!buffer.contains(&1));
+        assert!(buffer.contains(&3));
+    }
+
     fn as_vec<T>(buffer: &UniqueCircularBuffer<T>) -> Vec<T>
     where
         T: Clone,
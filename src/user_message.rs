@@ -0,0 +1,19 @@
+use crate::member::MemberId;
+use bytes::Bytes;
+
+/// Maximal size of a payload broadcast by the application.
+pub(crate) const MAX_USER_MESSAGE_SIZE: usize = 512;
+
+/// Opaque message broadcast by the application, piggybacked on the protocol messages.
+#[derive(Debug, Clone)]
+pub(crate) struct UserMessage {
+    pub(crate) origin: MemberId,
+    pub(crate) sequence_number: u64,
+    pub(crate) payload: Bytes,
+}
+
+impl PartialEq for UserMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.origin == other.origin && self.sequence_number == other.sequence_number
+    }
+}
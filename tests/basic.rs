@@ -1,8 +1,12 @@
-use failure;
-use membership;
-use membership::Node;
+use membership::{
+    ConfigError, Delegate, JoinError, KeyringError, MemberInfo, MembershipEvent, Node, ProtocolConfig, Readiness,
+    Registry, Transport,
+};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 mod common;
 use crate::common::*;
@@ -76,6 +80,604 @@ fn different_ports() -> TestResult {
     })
 }
 
+#[test]
+fn ipv6_members() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("[::1]:2345")?;
+        let address2 = SocketAddr::from_str("[::1]:3456")?;
+        let address3 = SocketAddr::from_str("[::1]:4567")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+        let mut node3 = Node::new(address3, Default::default());
+
+        node1.start()?;
+        node2.join(address1)?;
+        node3.join(address2)?;
+        advance_epochs(2);
+
+        for node in &[&node1, &node2, &node3] {
+            assert_eq_unordered(&[address1, address2, address3], &node.get_members()?);
+        }
+
+        stop_members(&mut [node1, node2, node3])
+    })
+}
+
+#[test]
+fn left_member_removed() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+
+        node1.start()?;
+        node2.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(&[address1, address2], &node1.get_members()?);
+
+        // No need to wait for suspicion, the member announces that it is leaving.
+        node2.stop()?;
+        assert_eq_unordered(&[address1], &node1.get_members()?);
+        assert_eq!(node1.get_dead_members_count()?, 1);
+
+        stop_members(&mut [node1])
+    })
+}
+
+#[test]
+fn user_messages_delivered() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+
+        node1.start()?;
+        node2.join(address1)?;
+        let receiver = node2.subscribe_broadcasts()?;
+        advance_epochs(1);
+
+        node1.broadcast(b"config changed".to_vec())?;
+        advance_epochs(2);
+
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![b"config changed".to_vec()]
+        );
+        assert!(node1.broadcast(vec![0u8; 513]).is_err());
+
+        stop_members(&mut [node1, node2])
+    })
+}
+
+#[test]
+fn metadata_disseminated() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+
+        node1.set_metadata(b"role=seed".to_vec())?;
+        node1.start()?;
+        node2.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(
+            &[(address1, b"role=seed".to_vec()), (address2, vec![])],
+            &node2.get_members_with_metadata()?,
+        );
+
+        node2.set_metadata(b"role=worker".to_vec())?;
+        advance_epochs(2);
+
+        assert_eq_unordered(
+            &[(address1, b"role=seed".to_vec()), (address2, b"role=worker".to_vec())],
+            &node1.get_members_with_metadata()?,
+        );
+        assert!(node1.set_metadata(vec![0u8; 129]).is_err());
+
+        stop_members(&mut [node1, node2])
+    })
+}
+
+#[test]
+fn join_through_any_seed() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let unreachable = SocketAddr::from_str("127.0.0.1:4567")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+
+        node1.start()?;
+        node2.join_any(&[unreachable, address1])?;
+        advance_epochs(2);
+
+        assert_eq_unordered(&[address1, address2], &node2.get_members()?);
+
+        stop_members(&mut [node1, node2])
+    })
+}
+
+#[test]
+fn join_fails_when_no_seed_answers() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address = SocketAddr::from_str("127.0.0.1:2345")?;
+        let unreachable = SocketAddr::from_str("127.0.0.1:4567")?;
+        let config = ProtocolConfig {
+            join_retry_timeout: Duration::from_secs(1),
+            join_max_attempts: 2,
+            ..Default::default()
+        };
+        let mut node = Node::new(address, config);
+
+        node.join_any(&[unreachable])?;
+
+        // The member stops on its own once all attempts fail.
+        std::thread::sleep(Duration::from_secs(4));
+        match node.stop().map_err(|e| e.downcast::<JoinError>()) {
+            Err(Ok(JoinError::NoSeedAnswered { attempts })) => assert_eq!(attempts, 2),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn join_without_seeds_fails() -> TestResult {
+    let mut node = Node::new(SocketAddr::from_str("127.0.0.1:2345")?, Default::default());
+
+    match node.join_any(&[]).map_err(|e| e.downcast::<JoinError>()) {
+        Err(Ok(JoinError::NoSeeds)) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    Ok(())
+}
+
+#[test]
+fn join_and_wait_returns_members() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let address3 = SocketAddr::from_str("127.0.0.1:4567")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+        let mut node3 = Node::new(address3, Default::default());
+
+        node1.start()?;
+        node2.join(address1)?;
+        advance_epochs(1);
+
+        let members = node3.join_and_wait(&[address1], Duration::from_secs(5))?;
+        assert_eq_unordered(&[address1, address2, address3], &members);
+
+        stop_members(&mut [node1, node2, node3])
+    })
+}
+
+#[test]
+fn join_and_wait_times_out() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address = SocketAddr::from_str("127.0.0.1:2345")?;
+        let unreachable = SocketAddr::from_str("127.0.0.1:4567")?;
+        let mut node = Node::new(address, Default::default());
+
+        match node.join_and_wait(&[unreachable], Duration::from_secs(2)) {
+            Err(e) => match e.downcast_ref::<JoinError>() {
+                Some(JoinError::Timeout) => {}
+                e => panic!("Unexpected error {:?}", e),
+            },
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        node.stop()
+    })
+}
+
+#[test]
+fn invalid_config_rejected() -> TestResult {
+    let config = ProtocolConfig {
+        ack_timeout: Duration::from_secs(10),
+        ..ProtocolConfig::lan()
+    };
+    let mut node = Node::new(SocketAddr::from_str("127.0.0.1:2345")?, config);
+
+    match node.start() {
+        Err(e) => assert!(e.downcast_ref::<ConfigError>().is_some()),
+        result => panic!("Unexpected result {:?}", result),
+    }
+    Ok(())
+}
+
+#[test]
+fn unauthenticated_member_ignored() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let address3 = SocketAddr::from_str("127.0.0.1:4567")?;
+        let config = |key: &[u8]| ProtocolConfig {
+            secret_key: Some(key.to_vec()),
+            ..Default::default()
+        };
+        let mut node1 = Node::new(address1, config(b"secret"));
+        let mut node2 = Node::new(address2, config(b"secret"));
+        let mut node3 = Node::new(address3, config(b"other"));
+
+        node1.start()?;
+        node2.join(address1)?;
+        node3.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(&[address1, address2], &node1.get_members()?);
+        assert_eq_unordered(&[address1, address2], &node2.get_members()?);
+        assert_eq_unordered(&[address3], &node3.get_members()?);
+        assert!(node1.get_metrics()?.unauthenticated_messages > 0);
+
+        stop_members(&mut [node1, node2, node3])
+    })
+}
+
+#[test]
+fn foreign_cluster_ignored() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let address3 = SocketAddr::from_str("127.0.0.1:4567")?;
+        let config = |cluster_name: &str| ProtocolConfig {
+            cluster_name: cluster_name.to_string(),
+            ..Default::default()
+        };
+        let mut node1 = Node::new(address1, config("production"));
+        let mut node2 = Node::new(address2, config("production"));
+        let mut node3 = Node::new(address3, config("staging"));
+
+        node1.start()?;
+        node2.join(address1)?;
+        node3.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(&[address1, address2], &node1.get_members()?);
+        assert_eq_unordered(&[address1, address2], &node2.get_members()?);
+        assert_eq_unordered(&[address3], &node3.get_members()?);
+        assert!(node1.get_metrics()?.foreign_cluster_messages > 0);
+
+        stop_members(&mut [node1, node2, node3])
+    })
+}
+
+#[test]
+fn membership_events_delivered() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+
+        node1.start()?;
+        let events = node1.subscribe()?;
+        node2.join(address1)?;
+        let timeout = ProtocolConfig::default().protocol_period * 2;
+        match events.recv_timeout(timeout)? {
+            MembershipEvent::Joined(member) => assert_eq!(member.address, address2),
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        node2.stop()?;
+        match events.recv_timeout(timeout)? {
+            MembershipEvent::Left(member) => assert_eq!(member.address, address2),
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        stop_members(&mut [node1])
+    })
+}
+
+struct Greeter {
+    greeting: Vec<u8>,
+    received: std::sync::mpsc::Sender<Vec<u8>>,
+}
+
+impl Delegate for Greeter {
+    fn node_metadata(&mut self, _limit: usize) -> Option<Vec<u8>> {
+        Some(self.greeting.clone())
+    }
+
+    fn piggyback_data(&mut self, _limit: usize) -> Vec<u8> {
+        self.greeting.clone()
+    }
+
+    fn notify_piggyback_data(&mut self, _sender: &MemberInfo, data: &[u8]) {
+        self.received.send(data.to_vec()).unwrap();
+    }
+}
+
+#[test]
+fn delegate_called() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::new(address1, Default::default());
+        let mut node2 = Node::new(address2, Default::default());
+        let (sender, received) = std::sync::mpsc::channel();
+        node1.set_delegate(Greeter {
+            greeting: b"hello".to_vec(),
+            received: sender.clone(),
+        });
+        node2.set_delegate(Greeter {
+            greeting: b"hi".to_vec(),
+            received: sender,
+        });
+
+        node1.start()?;
+        node2.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(
+            &[(address1, b"hello".to_vec()), (address2, b"hi".to_vec())],
+            &node1.get_members_with_metadata()?,
+        );
+        let received = received.try_iter().collect::<Vec<_>>();
+        assert!(received.contains(&b"hello".to_vec()));
+        assert!(received.contains(&b"hi".to_vec()));
+
+        stop_members(&mut [node1, node2])
+    })
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_node_joined() -> TestResult {
+    use membership::AsyncNode;
+
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = AsyncNode::new(address1, Default::default());
+        let mut node2 = AsyncNode::new(address2, Default::default());
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+
+        runtime.block_on(async {
+            node1.start().await?;
+            let mut events = node1.subscribe()?;
+            assert_eq_unordered(&[address1, address2], &node2.join(&[address1]).await?);
+            match events.next().await {
+                Some(MembershipEvent::Joined(member)) => assert_eq!(member.address, address2),
+                event => panic!("Unexpected event {:?}", event),
+            }
+            assert_eq_unordered(&[address1, address2], &node1.get_members().await?);
+
+            node2.stop().await?;
+            match events.next().await {
+                Some(MembershipEvent::Left(member)) => assert_eq!(member.address, address2),
+                event => panic!("Unexpected event {:?}", event),
+            }
+            node1.stop().await?;
+            assert_eq!(events.next().await, None);
+            Ok(())
+        })
+    })
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_node_join_fails_when_no_seed_answers() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address = SocketAddr::from_str("127.0.0.1:2345")?;
+        let unreachable = SocketAddr::from_str("127.0.0.1:4567")?;
+        let config = ProtocolConfig {
+            join_retry_timeout: Duration::from_secs(1),
+            join_max_attempts: 2,
+            ..Default::default()
+        };
+        let mut node = membership::AsyncNode::new(address, config);
+        let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+
+        match runtime
+            .block_on(node.join(&[unreachable]))
+            .map_err(|e| e.downcast::<JoinError>())
+        {
+            Err(Ok(JoinError::NoSeedAnswered { attempts })) => assert_eq!(attempts, 2),
+            result => panic!("Unexpected result {:?}", result),
+        }
+        Ok(())
+    })
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_node_join_without_seeds_fails() -> TestResult {
+    let mut node = membership::AsyncNode::new(SocketAddr::from_str("127.0.0.1:2345")?, Default::default());
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+
+    match runtime.block_on(node.join(&[])).map_err(|e| e.downcast::<JoinError>()) {
+        Err(Ok(JoinError::NoSeeds)) => {}
+        result => panic!("Unexpected result {:?}", result),
+    }
+    Ok(())
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+type Network = Arc<Mutex<HashMap<SocketAddr, Inbox>>>;
+
+/// Datagrams sent to a member, which is woken up through the readiness of its transport once it is registered.
+struct Inbox {
+    sender: std::sync::mpsc::Sender<Datagram>,
+    readiness: Option<Readiness>,
+}
+
+/// Transport delivering datagrams through channels, to members registered in the shared network.
+struct ChannelTransport {
+    address: SocketAddr,
+    network: Network,
+    receiver: std::sync::mpsc::Receiver<Datagram>,
+}
+
+impl ChannelTransport {
+    fn new(address: SocketAddr, network: &Network) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let inbox = Inbox {
+            sender,
+            readiness: None,
+        };
+        network.lock().unwrap().insert(address, inbox);
+        ChannelTransport {
+            address,
+            network: network.clone(),
+            receiver,
+        }
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn register(&mut self, registry: &Registry) -> std::io::Result<()> {
+        let readiness = registry.readiness()?;
+        // Datagrams might have been sent before the member started.
+        readiness.set_readable()?;
+        if let Some(inbox) = self.network.lock().unwrap().get_mut(&self.address) {
+            inbox.readiness = Some(readiness);
+        }
+        Ok(())
+    }
+
+    fn send_to(&self, buffer: &[u8], target: SocketAddr) -> std::io::Result<usize> {
+        if let Some(inbox) = self.network.lock().unwrap().get(&target) {
+            // Datagrams to stopped members are lost.
+            if inbox.sender.send((buffer.to_vec(), self.address)).is_ok() {
+                if let Some(ref readiness) = inbox.readiness {
+                    let _ = readiness.set_readable();
+                }
+            }
+        }
+        Ok(buffer.len())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        match self.receiver.try_recv() {
+            Ok((datagram, sender)) => {
+                buffer[..datagram.len()].copy_from_slice(&datagram);
+                Ok((datagram.len(), sender))
+            }
+            Err(_) => Err(std::io::ErrorKind::WouldBlock.into()),
+        }
+    }
+}
+
+#[test]
+fn custom_transport_used() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let network = Arc::new(Mutex::new(HashMap::new()));
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let mut node1 = Node::with_transport(address1, Default::default(), ChannelTransport::new(address1, &network));
+        let mut node2 = Node::with_transport(address2, Default::default(), ChannelTransport::new(address2, &network));
+
+        node1.start()?;
+        node2.join(address1)?;
+        advance_epochs(2);
+
+        assert_eq_unordered(&[address1, address2], &node1.get_members()?);
+        assert_eq_unordered(&[address1, address2], &node2.get_members()?);
+        // Nothing listens on the host network, so the members communicated only through the channels.
+        assert!(std::net::UdpSocket::bind(address1).is_ok());
+        assert!(std::net::TcpListener::bind(address1).is_ok());
+
+        stop_members(&mut [node1, node2])
+    })
+}
+
+#[test]
+fn encryption_keys_rotated() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let address3 = SocketAddr::from_str("127.0.0.1:4567")?;
+        let old_key = vec![1u8; 32];
+        let new_key = vec![2u8; 16];
+        let mut nodes = vec![
+            Node::new(address1, Default::default()),
+            Node::new(address2, Default::default()),
+            Node::new(address3, Default::default()),
+        ];
+        for node in nodes.iter_mut() {
+            node.install_key(old_key.clone())?;
+        }
+
+        nodes[0].start()?;
+        nodes[1].join(address1)?;
+        nodes[2].join(address1)?;
+        advance_epochs(2);
+
+        for node in &nodes {
+            assert_eq_unordered(&[address1, address2, address3], &node.get_members()?);
+        }
+
+        for node in nodes.iter_mut() {
+            node.install_key(new_key.clone())?;
+        }
+        for node in nodes.iter_mut() {
+            node.use_key(&new_key)?;
+        }
+        for node in nodes.iter_mut() {
+            node.remove_key(&old_key)?;
+        }
+        advance_epochs(2);
+
+        for node in &nodes {
+            assert_eq_unordered(&[address1, address2, address3], &node.get_members()?);
+            assert_eq!(node.get_metrics()?.undecryptable_messages, 0);
+        }
+        match nodes[0].remove_key(&new_key) {
+            Err(e) => assert!(e.downcast_ref::<KeyringError>().is_some()),
+            result => panic!("Unexpected result {:?}", result),
+        }
+
+        stop_members(&mut nodes)
+    })
+}
+
+#[test]
+fn group_keys_rotated() -> TestResult {
+    in_namespace(|| -> TestResult {
+        let address1 = SocketAddr::from_str("127.0.0.1:2345")?;
+        let address2 = SocketAddr::from_str("127.0.0.1:3456")?;
+        let address3 = SocketAddr::from_str("127.0.0.1:4567")?;
+        let old_key = vec![1u8; 32];
+        let new_key = vec![2u8; 32];
+        let mut nodes = vec![
+            Node::new(address1, ProtocolConfig::lan()),
+            Node::new(address2, ProtocolConfig::lan()),
+            Node::new(address3, ProtocolConfig::lan()),
+        ];
+        for node in nodes.iter_mut() {
+            node.install_key(old_key.clone())?;
+        }
+
+        nodes[0].start()?;
+        nodes[1].join(address1)?;
+        nodes[2].join(address1)?;
+        std::thread::sleep(ProtocolConfig::lan().protocol_period * 3);
+
+        let timeout = Duration::from_secs(10);
+        let report = nodes[0].install_group_key(new_key.clone(), timeout)?;
+        assert!(report.is_complete(), "{:?}", report);
+        assert_eq_unordered(&[address1, address2, address3], &report.succeeded);
+        assert!(nodes[1].use_group_key(&new_key, timeout)?.is_complete());
+        let report = nodes[2].remove_group_key(&old_key, timeout)?;
+        assert!(report.is_complete(), "{:?}", report);
+
+        for node in &nodes {
+            assert_eq_unordered(&[address1, address2, address3], &node.get_members()?);
+        }
+        let report = nodes[0].remove_group_key(&new_key, timeout)?;
+        assert_eq!(report.failed.len(), 3);
+
+        stop_members(&mut nodes)
+    })
+}
+
 #[test]
 fn many_notifications() -> TestResult {
     in_namespace(|| -> TestResult {
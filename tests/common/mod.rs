@@ -1,35 +1,33 @@
-use iptables;
 use membership::{Node, ProtocolConfig};
 use sloggers::terminal::TerminalLoggerBuilder;
 use sloggers::Build;
 use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::time::Duration;
 
 pub fn create_tun_interface(cidr: &str) {
     static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(1);
     let name = format!("tun{}", COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
     assert!(std::process::Command::new("ip")
-        .args(&["tuntap", "add", "mode", "tun", &name])
+        .args(["tuntap", "add", "mode", "tun", &name])
         .output()
         .unwrap()
         .status
         .success());
     assert!(std::process::Command::new("ip")
-        .args(&["address", "add", cidr, "dev", &name])
+        .args(["address", "add", cidr, "dev", &name])
         .output()
         .unwrap()
         .status
         .success());
     assert!(std::process::Command::new("ip")
-        .args(&["link", "set", &name, "up"])
+        .args(["link", "set", &name, "up"])
         .output()
         .unwrap()
         .status
         .success());
     assert!(std::process::Command::new("ping")
-        .args(&["-c1", "-W3", cidr.split('/').nth(0).unwrap()])
+        .args(["-c1", "-W3", cidr.split('/').next().unwrap()])
         .output()
         .unwrap()
         .status
@@ -43,13 +41,13 @@ pub fn unshare_netns() {
         }
     }
     assert!(std::process::Command::new("ip")
-        .args(&["address", "add", "127.0.0.1/8", "dev", "lo"])
+        .args(["address", "add", "127.0.0.1/8", "dev", "lo"])
         .output()
         .unwrap()
         .status
         .success());
     assert!(std::process::Command::new("ip")
-        .args(&["link", "set", "lo", "up"])
+        .args(["link", "set", "lo", "up"])
         .output()
         .unwrap()
         .status
@@ -154,24 +152,20 @@ pub fn stop_members(mss: &mut [membership::Node]) -> Result<(), failure::Error>
 }
 
 pub fn advance_epochs(num_epochs: u8) {
-    std::thread::sleep(Duration::from_secs(
-        ProtocolConfig::default().protocol_period * num_epochs as u64,
-    ));
+    std::thread::sleep(ProtocolConfig::default().protocol_period * num_epochs as u32);
 }
 
 pub fn block_member(member: &Node) {
     slog::info!(logger(), "blocking member: {}", member.bind_address().ip().to_string());
 
     let ipt = iptables::new(false).unwrap();
-    assert_eq!(
-        ipt.append(
+    assert!(ipt
+        .append(
             "filter",
             "OUTPUT",
-            &format!("--src {} -j DROP", member.bind_address().ip().to_string()),
+            &format!("--src {} -j DROP", member.bind_address().ip()),
         )
-        .unwrap(),
-        true
-    );
+        .unwrap());
 }
 
 pub fn unblock_member(member: &Node) {
@@ -182,15 +176,13 @@ pub fn unblock_member(member: &Node) {
     );
 
     let ipt = iptables::new(false).unwrap();
-    assert_eq!(
-        ipt.delete(
+    assert!(ipt
+        .delete(
             "filter",
             "OUTPUT",
-            &format!("--src {} -j DROP", member.bind_address().ip().to_string()),
+            &format!("--src {} -j DROP", member.bind_address().ip()),
         )
-        .unwrap(),
-        true
-    );
+        .unwrap());
 }
 
 fn logger() -> slog::Logger {